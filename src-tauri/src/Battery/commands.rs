@@ -0,0 +1,26 @@
+use super::{BatteryConfig, BatteryState, TauriBattery};
+use std::sync::Arc;
+use tauri::State;
+
+#[tauri::command]
+pub async fn battery_get_state(
+    battery: State<'_, Arc<TauriBattery>>,
+) -> Result<BatteryState, String> {
+    Ok(battery.state().await)
+}
+
+#[tauri::command]
+pub async fn battery_get_config(
+    battery: State<'_, Arc<TauriBattery>>,
+) -> Result<BatteryConfig, String> {
+    Ok(battery.config().await)
+}
+
+#[tauri::command]
+pub async fn battery_set_config(
+    config: BatteryConfig,
+    battery: State<'_, Arc<TauriBattery>>,
+) -> Result<(), String> {
+    battery.set_config(config).await;
+    Ok(())
+}
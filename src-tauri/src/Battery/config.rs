@@ -0,0 +1,19 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct BatteryConfig {
+    // onBatteryLow is sent when charge drops to this percentage:
+    pub low_threshold: f64,
+    // Charge must rise this much above low_threshold before it can fire again:
+    pub hysteresis: f64,
+}
+
+impl Default for BatteryConfig {
+    fn default() -> Self {
+        Self {
+            low_threshold: 15.0,
+            hysteresis: 2.0,
+        }
+    }
+}
@@ -0,0 +1,422 @@
+pub mod commands;
+mod config;
+
+pub use config::BatteryConfig;
+
+use crate::Bus::{keep_connected, Attempt, Availability, BusConfig, ReconnectPolicy, SharedBus};
+use crate::Emit;
+use crate::Log;
+use crate::Shutdown::Shutdown;
+use futures_util::StreamExt;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tauri::{AppHandle, Wry};
+use tokio::sync::mpsc;
+use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
+use zbus::fdo::PropertiesProxy;
+use zbus::names::InterfaceName;
+use zvariant::{OwnedObjectPath, OwnedValue, Value};
+
+const UPOWER_NAME: &str = "org.freedesktop.UPower";
+const UPOWER_PATH: &str = "/org/freedesktop/UPower";
+const DEVICE_INTERFACE: &str = "org.freedesktop.UPower.Device";
+// Composite battery of all batteries, as UPower shows it:
+const DISPLAY_DEVICE_PATH: &str = "/org/freedesktop/UPower/devices/DisplayDevice";
+pub const EMIT_EVENT: &str = "onBatteryUpdate";
+
+pub struct TauriBattery {
+    app_handle: AppHandle<Wry>,
+    config: RwLock<BatteryConfig>,
+    state: RwLock<BatteryState>,
+    // onBatteryLow was sent, until charge is back above threshold + hysteresis:
+    low_latched: RwLock<bool>,
+    bus: BusConfig,
+    shared_bus: Arc<SharedBus>,
+    reconnect: ReconnectPolicy,
+    availability: Availability,
+    shutdown: Arc<Shutdown>,
+}
+
+#[derive(Clone, Serialize, Debug, Default, PartialEq)]
+pub struct BatteryState {
+    present: bool,
+    percentage: f64,
+    // UPower state: "charging", "discharging", "empty", "full",
+    // "pending-charge", "pending-discharge" or "unknown"
+    state: String,
+    time_to_empty_s: i64,
+    time_to_full_s: i64,
+    // Each battery behind the composite, for dual-battery laptops:
+    batteries: Vec<BatteryDevice>,
+}
+
+#[derive(Clone, Serialize, Debug, Default, PartialEq)]
+pub struct BatteryDevice {
+    #[serde(skip)]
+    path: String,
+    // Kernel name, e.g. "BAT0":
+    name: String,
+    percentage: f64,
+    state: String,
+}
+
+impl TauriBattery {
+    pub fn new(
+        app_handle: AppHandle<Wry>,
+        shutdown: Arc<Shutdown>,
+        bus: BusConfig,
+        shared_bus: Arc<SharedBus>,
+        reconnect: ReconnectPolicy,
+    ) -> Arc<Self> {
+        let instance = Arc::new(Self {
+            app_handle,
+            config: RwLock::new(BatteryConfig::default()),
+            state: RwLock::new(BatteryState::default()),
+            low_latched: RwLock::new(false),
+            bus,
+            shared_bus,
+            reconnect,
+            availability: Availability::default(),
+            shutdown,
+        });
+
+        let cloned_instance = instance.clone();
+        instance.shutdown.spawn(async move {
+            let shutdown = cloned_instance.shutdown.token();
+            keep_connected(
+                "battery",
+                cloned_instance.reconnect.clone(),
+                &cloned_instance.availability,
+                &shutdown,
+                || cloned_instance.session(),
+            )
+            .await;
+        });
+
+        instance
+    }
+
+    pub async fn available(&self) -> bool {
+        self.availability.available().await
+    }
+
+    // Retry the bus after the reconnect policy gave up
+    pub async fn restart(&self) -> Result<(), String> {
+        self.availability.restart().await
+    }
+
+    // UPower or the whole system bus can go away, state is read again after
+    async fn session(&self) -> Attempt {
+        match self.start().await {
+            Ok(()) if self.shutdown.token().is_cancelled() => Attempt::Finished,
+            Ok(()) => {
+                Log::warn("battery", "TauriBattery: bus connection lost, reconnecting");
+                Attempt::Dropped
+            }
+            Err(err) => {
+                Log::error("battery", format!("TauriBattery err: {err}"));
+                Attempt::Failed
+            }
+        }
+    }
+
+    pub async fn state(&self) -> BatteryState {
+        self.state.read().await.clone()
+    }
+
+    pub async fn config(&self) -> BatteryConfig {
+        self.config.read().await.clone()
+    }
+
+    pub async fn set_config(&self, config: BatteryConfig) {
+        *self.config.write().await = config;
+    }
+
+    async fn start(&self) -> zbus::Result<()> {
+        // UPower lives on the system bus by default:
+        let connection = self.shared_bus.connect(&self.bus).await?;
+        let properties_proxy = PropertiesProxy::builder(&connection)
+            .destination(UPOWER_NAME)?
+            .path(DISPLAY_DEVICE_PATH)?
+            .build()
+            .await?;
+
+        let mut properties_changed = properties_proxy.receive_properties_changed().await?;
+
+        let props = properties_proxy
+            .get_all(InterfaceName::from_static_str_unchecked(DEVICE_INTERFACE))
+            .await?;
+        self.update(&props).await;
+
+        // Batteries can be hot-swapped, follow them all:
+        let upower = zbus::Proxy::new(&connection, UPOWER_NAME, UPOWER_PATH, UPOWER_NAME).await?;
+        let mut device_added = upower.receive_signal("DeviceAdded").await?;
+        let mut device_removed = upower.receive_signal("DeviceRemoved").await?;
+        let (device_sender, mut device_receiver) = mpsc::channel(64);
+        let mut watchers: HashMap<String, JoinHandle<()>> = HashMap::new();
+
+        let paths: Vec<OwnedObjectPath> = upower.call("EnumerateDevices", &()).await?;
+        for path in paths {
+            self.watch_device(&connection, path.to_string(), &device_sender, &mut watchers)
+                .await;
+        }
+
+        let shutdown = self.shutdown.token();
+        loop {
+            tokio::select! {
+                signal = properties_changed.next() => {
+                    let Some(signal) = signal else {
+                        break;
+                    };
+                    if let Some(changed) = changed_properties(&signal) {
+                        self.update(&changed).await;
+                    }
+                }
+                Some((path, changed)) = device_receiver.recv() => {
+                    self.update_device(&path, &changed).await;
+                }
+                Some(message) = device_added.next() => {
+                    if let Ok(path) = message.body::<OwnedObjectPath>() {
+                        let path = path.to_string();
+                        self.watch_device(&connection, path, &device_sender, &mut watchers).await;
+                    }
+                }
+                Some(message) = device_removed.next() => {
+                    if let Ok(path) = message.body::<OwnedObjectPath>() {
+                        if let Some(watcher) = watchers.remove(path.as_str()) {
+                            watcher.abort();
+                        }
+                        self.remove_device(path.as_str()).await;
+                    }
+                }
+                _ = shutdown.cancelled() => break,
+            }
+        }
+
+        for watcher in watchers.into_values() {
+            watcher.abort();
+        }
+        // Stream ended with the connection, next connect opens a new one:
+        if !shutdown.is_cancelled() {
+            self.shared_bus.invalidate(&self.bus, &connection).await;
+        }
+
+        Ok(())
+    }
+
+    // Track device if it is a battery, its changes go to sender
+    async fn watch_device(
+        &self,
+        connection: &zbus::Connection,
+        path: String,
+        sender: &mpsc::Sender<(String, HashMap<String, OwnedValue>)>,
+        watchers: &mut HashMap<String, JoinHandle<()>>,
+    ) {
+        if watchers.contains_key(&path) {
+            return;
+        }
+
+        let properties_proxy = match device_proxy(connection, &path).await {
+            Ok(properties_proxy) => properties_proxy,
+            Err(err) => {
+                Log::error("battery", format!("TauriBattery device err: {path}: {err}"));
+                return;
+            }
+        };
+        let mut properties_changed = match properties_proxy.receive_properties_changed().await {
+            Ok(properties_changed) => properties_changed,
+            Err(err) => {
+                Log::error("battery", format!("TauriBattery device err: {path}: {err}"));
+                return;
+            }
+        };
+        let props = match properties_proxy
+            .get_all(InterfaceName::from_static_str_unchecked(DEVICE_INTERFACE))
+            .await
+        {
+            Ok(props) => props,
+            Err(err) => {
+                Log::error("battery", format!("TauriBattery device err: {path}: {err}"));
+                return;
+            }
+        };
+
+        // Type 2 is a battery, PowerSupply excludes mice and headsets:
+        let is_battery = matches!(props.get("Type").map(|v| &**v), Some(Value::U32(2)));
+        let power_supply = matches!(
+            props.get("PowerSupply").map(|v| &**v),
+            Some(Value::Bool(true))
+        );
+        if !is_battery || !power_supply {
+            return;
+        }
+        self.update_device(&path, &props).await;
+
+        let sender = sender.clone();
+        let device_path = path.clone();
+        let watcher = tokio::spawn(async move {
+            while let Some(signal) = properties_changed.next().await {
+                if let Some(changed) = changed_properties(&signal) {
+                    if sender.send((device_path.clone(), changed)).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        watchers.insert(path, watcher);
+    }
+
+    async fn update_device(&self, path: &str, props: &HashMap<String, OwnedValue>) {
+        let mut state = self.state.write().await;
+        let previous = state.batteries.clone();
+
+        let index = match state.batteries.iter().position(|v| v.path == path) {
+            Some(index) => index,
+            None => {
+                state.batteries.push(BatteryDevice {
+                    path: path.to_string(),
+                    name: path.rsplit('/').next().unwrap_or(path).to_string(),
+                    ..BatteryDevice::default()
+                });
+                state.batteries.sort_by(|a, b| a.path.cmp(&b.path));
+                state.batteries.iter().position(|v| v.path == path).unwrap()
+            }
+        };
+
+        let battery = &mut state.batteries[index];
+        if let Some(Value::Str(v)) = props.get("NativePath").map(|v| &**v) {
+            if !v.is_empty() {
+                battery.name = v.to_string();
+            }
+        }
+        if let Some(Value::F64(v)) = props.get("Percentage").map(|v| &**v) {
+            battery.percentage = *v;
+        }
+        if let Some(Value::U32(v)) = props.get("State").map(|v| &**v) {
+            battery.state = state_name(*v).to_string();
+        }
+
+        if state.batteries != previous {
+            let _ = Emit::emit(&self.app_handle, "battery", EMIT_EVENT, state.clone());
+        }
+    }
+
+    async fn remove_device(&self, path: &str) {
+        let mut state = self.state.write().await;
+        let count = state.batteries.len();
+        state.batteries.retain(|v| v.path != path);
+
+        if state.batteries.len() != count {
+            let _ = Emit::emit(&self.app_handle, "battery", EMIT_EVENT, state.clone());
+        }
+    }
+
+    // Apply changed properties, send update and transition events
+    async fn update(&self, props: &HashMap<String, OwnedValue>) {
+        let mut state = self.state.write().await;
+        let previous = state.clone();
+
+        if let Some(Value::Bool(v)) = props.get("IsPresent").map(|v| &**v) {
+            state.present = *v;
+        }
+        if let Some(Value::F64(v)) = props.get("Percentage").map(|v| &**v) {
+            state.percentage = *v;
+        }
+        if let Some(Value::U32(v)) = props.get("State").map(|v| &**v) {
+            state.state = state_name(*v).to_string();
+        }
+        if let Some(Value::I64(v)) = props.get("TimeToEmpty").map(|v| &**v) {
+            state.time_to_empty_s = *v;
+        }
+        if let Some(Value::I64(v)) = props.get("TimeToFull").map(|v| &**v) {
+            state.time_to_full_s = *v;
+        }
+
+        if *state == previous {
+            return;
+        }
+
+        let _ = Emit::emit(&self.app_handle, "battery", EMIT_EVENT, state.clone());
+
+        let config = self.config.read().await.clone();
+        let mut low_latched = self.low_latched.write().await;
+        for event in transitions(&previous, &state, &config, &mut low_latched) {
+            let _ = Emit::emit(&self.app_handle, "battery", event, state.clone());
+        }
+    }
+}
+
+async fn device_proxy(
+    connection: &zbus::Connection,
+    path: &str,
+) -> zbus::Result<PropertiesProxy<'static>> {
+    PropertiesProxy::builder(connection)
+        .destination(UPOWER_NAME)?
+        .path(path.to_string())?
+        .build()
+        .await
+}
+
+// Changes of the Device interface, other interfaces are skipped
+fn changed_properties(
+    signal: &zbus::fdo::PropertiesChanged,
+) -> Option<HashMap<String, OwnedValue>> {
+    let args = signal.args().ok()?;
+    if args.interface_name().as_str() != DEVICE_INTERFACE {
+        return None;
+    }
+
+    Some(
+        args.changed_properties()
+            .iter()
+            .map(|(key, value)| (key.to_string(), OwnedValue::from(value.clone())))
+            .collect(),
+    )
+}
+
+fn state_name(state: u32) -> &'static str {
+    match state {
+        1 => "charging",
+        2 => "discharging",
+        3 => "empty",
+        4 => "full",
+        5 => "pending-charge",
+        6 => "pending-discharge",
+        _ => "unknown",
+    }
+}
+
+// Discrete events between two states. Low is latched and released only
+// above threshold + hysteresis, so charge around the threshold doesn't flap
+fn transitions(
+    previous: &BatteryState,
+    current: &BatteryState,
+    config: &BatteryConfig,
+    low_latched: &mut bool,
+) -> Vec<&'static str> {
+    let mut events = Vec::new();
+
+    if current.state != previous.state {
+        match current.state.as_str() {
+            "charging" => events.push("onBatteryCharging"),
+            "discharging" => events.push("onBatteryDischarging"),
+            "full" => events.push("onBatteryFull"),
+            _ => {}
+        }
+    }
+
+    if !*low_latched
+        && current.state != "charging"
+        && current.percentage > 0.0
+        && current.percentage <= config.low_threshold
+    {
+        *low_latched = true;
+        events.push("onBatteryLow");
+    } else if *low_latched && current.percentage > config.low_threshold + config.hysteresis {
+        *low_latched = false;
+    }
+
+    events
+}
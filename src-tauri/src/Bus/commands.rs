@@ -0,0 +1,24 @@
+use super::{BusConfig, BusPreferences, ReconnectPolicy};
+
+#[tauri::command]
+pub async fn module_get_buses() -> Result<BusPreferences, String> {
+    Ok(BusPreferences::load())
+}
+
+// Stored for the next start, running modules keep their connection
+#[tauri::command]
+pub async fn module_set_bus(module: String, bus: BusConfig) -> Result<(), String> {
+    let mut preferences = BusPreferences::load();
+    preferences.set(&module, bus)?;
+    preferences.save()
+}
+
+// Stored for the next start like buses
+#[tauri::command]
+pub async fn module_set_reconnect_policy(policy: ReconnectPolicy) -> Result<(), String> {
+    policy.validate()?;
+
+    let mut preferences = BusPreferences::load();
+    preferences.reconnect = policy;
+    preferences.save()
+}
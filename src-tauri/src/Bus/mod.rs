@@ -0,0 +1,126 @@
+pub mod commands;
+mod reconnect;
+mod shared;
+
+pub use reconnect::{keep_connected, Attempt, Availability, Backoff, ReconnectPolicy};
+pub use shared::SharedBus;
+
+use crate::Log;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use zbus::{Address, Connection, ConnectionBuilder};
+
+// Which bus a module connects to
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase", tag = "type", content = "address")]
+pub enum BusConfig {
+    Session,
+    System,
+    // Custom D-Bus address, e.g. "unix:path=/run/user/1000/bus":
+    Address(String),
+}
+
+impl BusConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            BusConfig::Address(address) => Address::from_str(address)
+                .map(|_| ())
+                .map_err(|e| format!("Invalid D-Bus address {address}: {e}")),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn builder(&self) -> zbus::Result<ConnectionBuilder<'static>> {
+        match self {
+            BusConfig::Session => ConnectionBuilder::session(),
+            BusConfig::System => ConnectionBuilder::system(),
+            BusConfig::Address(address) => ConnectionBuilder::address(address.as_str()),
+        }
+    }
+
+    pub async fn connect(&self) -> zbus::Result<Connection> {
+        self.builder()?.build().await
+    }
+}
+
+// Bus of every module, read on start. Changes apply on the next start.
+// Modules on the same bus share its connection, see SharedBus
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct BusPreferences {
+    pub media_player: BusConfig,
+    pub system_tray: BusConfig,
+    pub dbus_bridge: BusConfig,
+    pub battery: BusConfig,
+    // Shared by modules that reconnect to their bus:
+    pub reconnect: ReconnectPolicy,
+}
+
+impl Default for BusPreferences {
+    fn default() -> Self {
+        Self {
+            media_player: BusConfig::Session,
+            system_tray: BusConfig::Session,
+            dbus_bridge: BusConfig::Session,
+            battery: BusConfig::System,
+            reconnect: ReconnectPolicy::default(),
+        }
+    }
+}
+
+impl BusPreferences {
+    // Broken or missing file gives defaults, modules must start anyway
+    pub fn load() -> Self {
+        let content = match fs::read_to_string(preferences_path()) {
+            Ok(content) => content,
+            Err(_) => return Self::default(),
+        };
+
+        serde_json::from_str(&content).unwrap_or_else(|err| {
+            Log::error("bus", format!("BusPreferences err: {err}"));
+            Self::default()
+        })
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let path = preferences_path();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+
+        let content = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(path, content).map_err(|e| e.to_string())
+    }
+
+    pub fn set(&mut self, module: &str, bus: BusConfig) -> Result<(), String> {
+        bus.validate()?;
+
+        let target = match module {
+            "media_player" => &mut self.media_player,
+            "system_tray" => &mut self.system_tray,
+            "dbus_bridge" => &mut self.dbus_bridge,
+            "battery" => &mut self.battery,
+            _ => return Err(format!("Unknown module {module}")),
+        };
+        *target = bus;
+
+        Ok(())
+    }
+}
+
+fn preferences_path() -> PathBuf {
+    config_path("buses.json")
+}
+
+// File in our directory below XDG_CONFIG_HOME
+pub fn config_path(file: &str) -> PathBuf {
+    let config_home = env::var("XDG_CONFIG_HOME")
+        .ok()
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| format!("{}/.config", env::var("HOME").unwrap_or_default()));
+
+    PathBuf::from(config_home).join("opentopbar").join(file)
+}
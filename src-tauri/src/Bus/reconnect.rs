@@ -0,0 +1,313 @@
+use crate::Log;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio::sync::{Notify, RwLock};
+use tokio_util::sync::CancellationToken;
+
+// How a module retries a lost bus: delay doubles from base to cap. Without
+// limits it retries forever, after a limit it waits for restart_module
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(default)]
+pub struct ReconnectPolicy {
+    pub base_ms: u64,
+    pub cap_ms: u64,
+    // Failed attempts in a row, and time since the first one:
+    pub max_attempts: Option<u32>,
+    pub max_duration_ms: Option<u64>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            base_ms: 1000,
+            cap_ms: 30000,
+            max_attempts: None,
+            max_duration_ms: None,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.base_ms == 0 || self.cap_ms < self.base_ms {
+            return Err(format!(
+                "Invalid reconnect backoff {}..{} ms",
+                self.base_ms, self.cap_ms
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Retry state of one reconnect loop
+pub struct Backoff {
+    policy: ReconnectPolicy,
+    attempts: u32,
+    first_failure: Option<Instant>,
+}
+
+impl Backoff {
+    pub fn new(policy: ReconnectPolicy) -> Self {
+        Self {
+            policy,
+            attempts: 0,
+            first_failure: None,
+        }
+    }
+
+    // Connected again, limits start over
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+        self.first_failure = None;
+    }
+
+    // Delay before the next attempt, None once the policy gives up
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        let first_failure = *self.first_failure.get_or_insert_with(Instant::now);
+        if self.policy.max_attempts.is_some_and(|v| self.attempts >= v) {
+            return None;
+        }
+        if let Some(max_duration_ms) = self.policy.max_duration_ms {
+            if first_failure.elapsed() >= Duration::from_millis(max_duration_ms) {
+                return None;
+            }
+        }
+
+        let delay = self
+            .policy
+            .base_ms
+            .saturating_mul(1 << self.attempts.min(16))
+            .min(self.policy.cap_ms);
+        self.attempts += 1;
+
+        Some(Duration::from_millis(delay))
+    }
+}
+
+// How one connection of a reconnecting module ended
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Attempt {
+    // Was connected until the bus went away, backoff starts over:
+    Dropped,
+    // Couldn't connect or set up:
+    Failed,
+    // Done for good, e.g. on shutdown, no retry:
+    Finished,
+}
+
+// Set once the policy gave up, until restart_module
+#[derive(Default)]
+pub struct Availability {
+    unavailable: RwLock<bool>,
+    restart: Notify,
+}
+
+impl Availability {
+    pub async fn available(&self) -> bool {
+        !*self.unavailable.read().await
+    }
+
+    pub async fn restart(&self) -> Result<(), String> {
+        if !*self.unavailable.read().await {
+            return Err("Module is connected or still reconnecting".to_string());
+        }
+        self.restart.notify_one();
+        Ok(())
+    }
+}
+
+// Runs attempt after attempt with backoff between them. Once the policy
+// gives up, the module is unavailable until restart or shutdown
+pub async fn keep_connected<F, Fut>(
+    subsystem: &str,
+    policy: ReconnectPolicy,
+    availability: &Availability,
+    shutdown: &CancellationToken,
+    mut attempt: F,
+) where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Attempt>,
+{
+    let mut backoff = Backoff::new(policy);
+    loop {
+        match attempt().await {
+            Attempt::Finished => break,
+            Attempt::Dropped => backoff.reset(),
+            Attempt::Failed => {}
+        }
+        if shutdown.is_cancelled() {
+            break;
+        }
+
+        let Some(delay) = backoff.next_delay() else {
+            Log::warn(
+                subsystem,
+                format!("{subsystem}: reconnect gave up, waiting for restart_module"),
+            );
+            *availability.unavailable.write().await = true;
+
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = availability.restart.notified() => {}
+            }
+            *availability.unavailable.write().await = false;
+            backoff.reset();
+            continue;
+        };
+
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = tokio::time::sleep(delay) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{keep_connected, Attempt, Availability, Backoff, ReconnectPolicy};
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio_util::sync::CancellationToken;
+
+    fn delays(backoff: &mut Backoff, count: usize) -> Vec<Option<u64>> {
+        (0..count)
+            .map(|_| backoff.next_delay().map(|v| v.as_millis() as u64))
+            .collect()
+    }
+
+    #[test]
+    fn delay_doubles_up_to_cap() {
+        let mut backoff = Backoff::new(ReconnectPolicy {
+            base_ms: 100,
+            cap_ms: 1000,
+            ..ReconnectPolicy::default()
+        });
+
+        assert_eq!(
+            delays(&mut backoff, 6),
+            [100, 200, 400, 800, 1000, 1000].map(Some)
+        );
+    }
+
+    #[test]
+    fn delay_stays_at_cap_after_many_attempts() {
+        let mut backoff = Backoff::new(ReconnectPolicy::default());
+        let last = delays(&mut backoff, 100).pop().flatten();
+        assert_eq!(last, Some(30000));
+    }
+
+    #[test]
+    fn reset_starts_over() {
+        let mut backoff = Backoff::new(ReconnectPolicy {
+            base_ms: 100,
+            cap_ms: 1000,
+            max_attempts: Some(2),
+            max_duration_ms: None,
+        });
+
+        assert_eq!(delays(&mut backoff, 3), [Some(100), Some(200), None]);
+        backoff.reset();
+        assert_eq!(delays(&mut backoff, 1), [Some(100)]);
+    }
+
+    #[test]
+    fn gives_up_after_max_duration() {
+        let mut backoff = Backoff::new(ReconnectPolicy {
+            max_duration_ms: Some(0),
+            ..ReconnectPolicy::default()
+        });
+        assert_eq!(backoff.next_delay(), None);
+    }
+
+    #[test]
+    fn invalid_policy_is_rejected() {
+        let policy = |base_ms, cap_ms| ReconnectPolicy {
+            base_ms,
+            cap_ms,
+            ..ReconnectPolicy::default()
+        };
+        assert!(policy(0, 1000).validate().is_err());
+        assert!(policy(1000, 100).validate().is_err());
+        assert!(policy(100, 100).validate().is_ok());
+    }
+
+    // Dead bus: attempts stop at the limit until restart
+    #[tokio::test]
+    async fn loop_stops_after_limit() {
+        let sessions = Arc::new(AtomicU32::new(0));
+        let availability = Arc::new(Availability::default());
+        let shutdown = CancellationToken::new();
+
+        let task = tokio::spawn({
+            let (sessions, availability, shutdown) =
+                (sessions.clone(), availability.clone(), shutdown.clone());
+            async move {
+                keep_connected("test", policy(Some(3)), &availability, &shutdown, || {
+                    sessions.fetch_add(1, Ordering::SeqCst);
+                    async { Attempt::Failed }
+                })
+                .await;
+            }
+        });
+
+        while availability.available().await {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        // First attempt and three retries:
+        assert_eq!(sessions.load(Ordering::SeqCst), 4);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(sessions.load(Ordering::SeqCst), 4);
+
+        availability.restart().await.unwrap();
+        while sessions.load(Ordering::SeqCst) < 8 || availability.available().await {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        assert_eq!(sessions.load(Ordering::SeqCst), 8);
+
+        shutdown.cancel();
+        task.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn restart_needs_unavailable_module() {
+        assert!(Availability::default().restart().await.is_err());
+    }
+
+    fn policy(max_attempts: Option<u32>) -> ReconnectPolicy {
+        ReconnectPolicy {
+            base_ms: 1,
+            cap_ms: 1,
+            max_attempts,
+            max_duration_ms: None,
+        }
+    }
+
+    // Connection drops once: the session is set up again, then the module
+    // finishes. What a session restores is tested by the modules
+    #[tokio::test]
+    async fn dropped_connection_runs_session_again() {
+        let sessions = AtomicU32::new(0);
+        let availability = Availability::default();
+
+        keep_connected(
+            "test",
+            policy(Some(1)),
+            &availability,
+            &CancellationToken::new(),
+            || async {
+                match sessions.fetch_add(1, Ordering::SeqCst) {
+                    0 => Attempt::Failed,
+                    1 => Attempt::Dropped,
+                    _ => Attempt::Finished,
+                }
+            },
+        )
+        .await;
+
+        // The drop started the limit over, so the one retry was left:
+        assert_eq!(sessions.load(Ordering::SeqCst), 3);
+        assert!(availability.available().await);
+    }
+}
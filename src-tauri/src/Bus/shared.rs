@@ -0,0 +1,43 @@
+use super::BusConfig;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use zbus::Connection;
+
+// One connection per bus, opened by the first module that needs it. Modules
+// only add their own match rules and filter their streams, so rules of
+// one module never replace the rules of another
+#[derive(Default)]
+pub struct SharedBus {
+    connections: Mutex<HashMap<BusConfig, Connection>>,
+}
+
+impl SharedBus {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    // Lock is held while connecting, so modules starting together share it
+    pub async fn connect(&self, bus: &BusConfig) -> zbus::Result<Connection> {
+        let mut connections = self.connections.lock().await;
+        if let Some(connection) = connections.get(bus) {
+            return Ok(connection.clone());
+        }
+
+        let connection = bus.connect().await?;
+        connections.insert(bus.clone(), connection.clone());
+        Ok(connection)
+    }
+
+    // Connection is dead, next connect opens a new one. Kept when another
+    // module already replaced it
+    pub async fn invalidate(&self, bus: &BusConfig, connection: &Connection) {
+        let mut connections = self.connections.lock().await;
+        let current = connections
+            .get(bus)
+            .is_some_and(|v| v.unique_name() == connection.unique_name());
+        if current {
+            connections.remove(bus);
+        }
+    }
+}
@@ -0,0 +1,19 @@
+use super::TauriDBusBridge;
+use std::sync::Arc;
+use tauri::State;
+
+#[tauri::command]
+pub async fn dbus_add_match(
+    rule: String,
+    dbus_bridge: State<'_, Arc<TauriDBusBridge>>,
+) -> Result<(), String> {
+    dbus_bridge.add_match(&rule).await
+}
+
+#[tauri::command]
+pub async fn dbus_remove_match(
+    rule: String,
+    dbus_bridge: State<'_, Arc<TauriDBusBridge>>,
+) -> Result<(), String> {
+    dbus_bridge.remove_match(&rule).await
+}
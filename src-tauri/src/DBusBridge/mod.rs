@@ -0,0 +1,296 @@
+pub mod commands;
+
+use crate::Bus::{keep_connected, Attempt, Availability, BusConfig, ReconnectPolicy};
+use crate::Emit;
+use crate::Log;
+use crate::Shutdown::Shutdown;
+use futures_util::StreamExt;
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Wry};
+use tokio::sync::RwLock;
+use zbus::fdo::DBusProxy;
+use zbus::{Connection, MessageStream, MessageType, OwnedMatchRule};
+use zvariant::{Basic, Dict, OwnedValue, Structure, Value};
+
+// Forwarded messages per second, the rest is dropped:
+pub const MAX_MESSAGES_PER_SECOND: u32 = 50;
+pub const EMIT_EVENT: &str = "onDBusMessage";
+
+pub struct TauriDBusBridge {
+    app_handle: AppHandle<Wry>,
+    connection: RwLock<Option<Connection>>,
+    rules: RwLock<Vec<OwnedMatchRule>>,
+    bus: BusConfig,
+    reconnect: ReconnectPolicy,
+    availability: Availability,
+    shutdown: Arc<Shutdown>,
+}
+
+#[derive(Clone, Serialize, Debug)]
+struct DBusMessageStruct {
+    sender: String,
+    path: String,
+    interface: String,
+    member: String,
+    body: JsonValue,
+}
+
+impl TauriDBusBridge {
+    pub fn new(
+        app_handle: AppHandle<Wry>,
+        shutdown: Arc<Shutdown>,
+        bus: BusConfig,
+        reconnect: ReconnectPolicy,
+    ) -> Arc<Self> {
+        let instance = Arc::new(Self {
+            app_handle,
+            connection: RwLock::new(None),
+            rules: RwLock::new(Vec::new()),
+            bus,
+            reconnect,
+            availability: Availability::default(),
+            shutdown,
+        });
+
+        let cloned_instance = instance.clone();
+        instance.shutdown.spawn(async move {
+            let shutdown = cloned_instance.shutdown.token();
+            keep_connected(
+                "dbus_bridge",
+                cloned_instance.reconnect.clone(),
+                &cloned_instance.availability,
+                &shutdown,
+                || cloned_instance.session(),
+            )
+            .await;
+        });
+
+        instance
+    }
+
+    pub async fn available(&self) -> bool {
+        self.availability.available().await
+    }
+
+    // Retry the bus after the reconnect policy gave up
+    pub async fn restart(&self) -> Result<(), String> {
+        self.availability.restart().await
+    }
+
+    async fn session(&self) -> Attempt {
+        let result = self.start().await;
+        *self.connection.write().await = None;
+
+        match result {
+            Ok(()) if self.shutdown.token().is_cancelled() => Attempt::Finished,
+            Ok(()) => {
+                Log::warn(
+                    "dbus_bridge",
+                    "TauriDBusBridge: bus connection lost, reconnecting",
+                );
+                Attempt::Dropped
+            }
+            Err(err) => {
+                Log::error("dbus_bridge", format!("TauriDBusBridge err: {err}"));
+                Attempt::Failed
+            }
+        }
+    }
+
+    pub async fn add_match(&self, rule: &str) -> Result<(), String> {
+        let rule = parse_rule(rule)?;
+        let mut rules = self.rules.write().await;
+        if rules.contains(&rule) {
+            return Ok(());
+        }
+
+        let dbus_proxy = self.dbus_proxy().await?;
+        dbus_proxy
+            .add_match_rule(rule.inner().clone())
+            .await
+            .map_err(|e| e.to_string())?;
+
+        rules.push(rule);
+        Ok(())
+    }
+
+    pub async fn remove_match(&self, rule: &str) -> Result<(), String> {
+        let rule = parse_rule(rule)?;
+        let mut rules = self.rules.write().await;
+        let index = rules
+            .iter()
+            .position(|v| *v == rule)
+            .ok_or_else(|| "Match rule is not installed".to_string())?;
+
+        let dbus_proxy = self.dbus_proxy().await?;
+        dbus_proxy
+            .remove_match_rule(rule.inner().clone())
+            .await
+            .map_err(|e| e.to_string())?;
+
+        rules.remove(index);
+        Ok(())
+    }
+
+    async fn dbus_proxy(&self) -> Result<DBusProxy<'static>, String> {
+        let connection = self.connection.read().await;
+        let connection = connection
+            .as_ref()
+            .ok_or_else(|| "D-Bus connection is not ready".to_string())?;
+
+        DBusProxy::new(connection).await.map_err(|e| e.to_string())
+    }
+
+    async fn start(&self) -> zbus::Result<()> {
+        // Own connection, so only messages of user rules are received:
+        let connection = self.bus.connect().await?;
+        let mut stream = MessageStream::from(connection.clone());
+
+        // Rules added before a reconnect, held so none is added meanwhile:
+        let rules = self.rules.read().await;
+        let dbus_proxy = DBusProxy::new(&connection).await?;
+        for rule in rules.iter() {
+            dbus_proxy.add_match_rule(rule.inner().clone()).await?;
+        }
+        *self.connection.write().await = Some(connection);
+        drop(rules);
+
+        // Simple rate limit by one second windows:
+        let mut window_start = Instant::now();
+        let mut window_count = 0;
+        let mut dropped = 0;
+
+        let shutdown = self.shutdown.token();
+        while let Some(event_message) = tokio::select! {
+            event_message = stream.next() => event_message,
+            _ = shutdown.cancelled() => None,
+        } {
+            if let Ok(event_message) = event_message {
+                let rules = self.rules.read().await;
+                if !rules
+                    .iter()
+                    .any(|rule| rule.matches(&event_message).unwrap_or_default())
+                {
+                    continue;
+                }
+                drop(rules);
+
+                if window_start.elapsed() >= Duration::from_secs(1) {
+                    if dropped > 0 {
+                        Log::warn(
+                            "dbus_bridge",
+                            format!("TauriDBusBridge rate limit: dropped {dropped} messages"),
+                        );
+                    }
+                    window_start = Instant::now();
+                    window_count = 0;
+                    dropped = 0;
+                }
+
+                if window_count >= MAX_MESSAGES_PER_SECOND {
+                    dropped += 1;
+                    continue;
+                }
+                window_count += 1;
+
+                let header = event_message.header()?;
+                let body = match event_message.body::<Structure>() {
+                    Ok(body) => JsonValue::Array(body.fields().iter().map(value_to_json).collect()),
+                    Err(_) => JsonValue::Array(Vec::new()),
+                };
+
+                let message = DBusMessageStruct {
+                    sender: optional_to_string(header.sender().ok().flatten()),
+                    path: optional_to_string(header.path().ok().flatten()),
+                    interface: optional_to_string(header.interface().ok().flatten()),
+                    member: optional_to_string(header.member().ok().flatten()),
+                    body,
+                };
+
+                let _ = Emit::emit(&self.app_handle, "dbus_bridge", EMIT_EVENT, message);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_rule(rule: &str) -> Result<OwnedMatchRule, String> {
+    // Parser only knows standard keys, so eavesdrop is rejected here:
+    let rule = OwnedMatchRule::try_from(rule).map_err(|e| e.to_string())?;
+    if rule.msg_type() != Some(MessageType::Signal) {
+        return Err("Only type='signal' match rules are allowed".to_string());
+    }
+
+    Ok(rule)
+}
+
+fn optional_to_string(value: Option<&impl ToString>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+// Convert any D-Bus value to JSON, recursively
+pub fn value_to_json(value: &Value) -> JsonValue {
+    match value {
+        Value::U8(v) => JsonValue::from(*v),
+        Value::Bool(v) => JsonValue::from(*v),
+        Value::I16(v) => JsonValue::from(*v),
+        Value::U16(v) => JsonValue::from(*v),
+        Value::I32(v) => JsonValue::from(*v),
+        Value::U32(v) => JsonValue::from(*v),
+        Value::I64(v) => JsonValue::from(*v),
+        Value::U64(v) => JsonValue::from(*v),
+        Value::F64(v) => JsonValue::from(*v),
+        Value::Str(v) => JsonValue::from(v.as_str()),
+        Value::Signature(v) => JsonValue::from(v.as_str()),
+        Value::ObjectPath(v) => JsonValue::from(v.as_str()),
+        Value::Value(v) => value_to_json(v),
+        Value::Array(arr) => JsonValue::Array(arr.iter().map(value_to_json).collect()),
+        Value::Dict(dict) => dict_to_json(dict),
+        Value::Structure(structure) => {
+            JsonValue::Array(structure.fields().iter().map(value_to_json).collect())
+        }
+        // Descriptors mean nothing outside of this process:
+        _ => JsonValue::Null,
+    }
+}
+
+fn dict_to_json(dict: &Dict) -> JsonValue {
+    // Dict has no iterator, convert through HashMap by key type:
+    let key_type = dict.full_signature().as_str().chars().nth(2);
+    let entries = match key_type {
+        Some('s') => dict_entries::<String>(dict),
+        Some('y') => dict_entries::<u8>(dict),
+        Some('b') => dict_entries::<bool>(dict),
+        Some('n') => dict_entries::<i16>(dict),
+        Some('q') => dict_entries::<u16>(dict),
+        Some('i') => dict_entries::<i32>(dict),
+        Some('u') => dict_entries::<u32>(dict),
+        Some('x') => dict_entries::<i64>(dict),
+        Some('t') => dict_entries::<u64>(dict),
+        _ => None,
+    };
+
+    match entries {
+        Some(entries) => JsonValue::Object(entries.into_iter().collect()),
+        None => JsonValue::Null,
+    }
+}
+
+fn dict_entries<K>(dict: &Dict) -> Option<Vec<(String, JsonValue)>>
+where
+    K: Basic + for<'k> TryFrom<Value<'k>> + Hash + Eq + ToString,
+    for<'k> <K as TryFrom<Value<'k>>>::Error: Into<zvariant::Error>,
+{
+    let map = HashMap::<K, OwnedValue>::try_from(dict.clone()).ok()?;
+    Some(
+        map.iter()
+            .map(|(k, v)| (k.to_string(), value_to_json(v)))
+            .collect(),
+    )
+}
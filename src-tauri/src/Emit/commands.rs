@@ -0,0 +1,21 @@
+use super::{EmitTarget, EmitTargets};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tauri::State;
+
+// Modules without an entry broadcast
+#[tauri::command]
+pub async fn module_get_emit_targets(
+    targets: State<'_, Arc<EmitTargets>>,
+) -> Result<HashMap<String, EmitTarget>, String> {
+    Ok(targets.all())
+}
+
+#[tauri::command]
+pub async fn module_set_emit_target(
+    module: String,
+    target: EmitTarget,
+    targets: State<'_, Arc<EmitTargets>>,
+) -> Result<(), String> {
+    targets.set(&module, target)
+}
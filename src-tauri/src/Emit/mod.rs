@@ -0,0 +1,71 @@
+pub mod commands;
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use tauri::{AppHandle, Emitter, Manager, Wry};
+
+// Where a module sends its events: every window, or one window by label
+// (e.g. a desktop now-playing widget next to the panel)
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase", tag = "type", content = "label")]
+pub enum EmitTarget {
+    #[default]
+    Broadcast,
+    Window(String),
+}
+
+// Target of every module, changes apply to the next event
+#[derive(Default)]
+pub struct EmitTargets {
+    targets: RwLock<HashMap<String, EmitTarget>>,
+}
+
+impl EmitTargets {
+    pub fn get(&self, module: &str) -> EmitTarget {
+        self.targets
+            .read()
+            .unwrap()
+            .get(module)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn all(&self) -> HashMap<String, EmitTarget> {
+        self.targets.read().unwrap().clone()
+    }
+
+    pub fn set(&self, module: &str, target: EmitTarget) -> Result<(), String> {
+        match module {
+            "media_player" | "system_tray" | "dbus_bridge" | "battery" | "focused_window" => {}
+            _ => return Err(format!("Unknown module {module}")),
+        }
+        if target == EmitTarget::Window(String::new()) {
+            return Err("Window label is empty".to_string());
+        }
+
+        self.targets
+            .write()
+            .unwrap()
+            .insert(module.to_string(), target);
+        Ok(())
+    }
+}
+
+// Emit event of module to its target, broadcast when targets aren't managed
+pub fn emit<S: Serialize + Clone>(
+    app_handle: &AppHandle<Wry>,
+    module: &str,
+    event: &str,
+    payload: S,
+) -> tauri::Result<()> {
+    let target = match app_handle.try_state::<Arc<EmitTargets>>() {
+        Some(targets) => targets.get(module),
+        None => EmitTarget::Broadcast,
+    };
+
+    match target {
+        EmitTarget::Broadcast => app_handle.emit(event, payload),
+        EmitTarget::Window(label) => app_handle.emit_to(label.as_str(), event, payload),
+    }
+}
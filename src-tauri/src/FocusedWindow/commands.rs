@@ -0,0 +1,10 @@
+use super::{FocusedWindow, TauriFocusedWindow};
+use std::sync::Arc;
+use tauri::State;
+
+#[tauri::command]
+pub async fn focused_window_get(
+    focused_window: State<'_, Arc<TauriFocusedWindow>>,
+) -> Result<FocusedWindow, String> {
+    Ok(focused_window.state())
+}
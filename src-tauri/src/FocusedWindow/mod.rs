@@ -0,0 +1,210 @@
+pub mod commands;
+
+use crate::Emit;
+use crate::Log;
+use crate::Shutdown::Shutdown;
+use serde::Serialize;
+use std::error::Error;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+use tauri::{AppHandle, Wry};
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{
+    Atom, AtomEnum, ChangeWindowAttributesAux, ConnectionExt, EventMask, Window,
+};
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+
+pub const EMIT_EVENT: &str = "onFocusedWindow";
+// X events are polled, so exit is noticed without a blocking wait:
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+pub struct TauriFocusedWindow {
+    app_handle: AppHandle<Wry>,
+    state: RwLock<FocusedWindow>,
+    shutdown: Arc<Shutdown>,
+}
+
+#[derive(Clone, Serialize, Debug, Default, PartialEq)]
+pub struct FocusedWindow {
+    title: String,
+    // Class of WM_CLASS, e.g. "firefox":
+    app_id: String,
+}
+
+struct Atoms {
+    net_active_window: Atom,
+    net_wm_name: Atom,
+    utf8_string: Atom,
+}
+
+impl TauriFocusedWindow {
+    // X11 (and XWayland windows) only, without a display the module stays idle
+    pub fn new(app_handle: AppHandle<Wry>, shutdown: Arc<Shutdown>) -> Arc<Self> {
+        let instance = Arc::new(Self {
+            app_handle,
+            state: RwLock::new(FocusedWindow::default()),
+            shutdown,
+        });
+
+        let cloned_instance = instance.clone();
+        instance.shutdown.track(thread::spawn(move || {
+            if let Err(err) = cloned_instance.start() {
+                Log::error("focused_window", format!("TauriFocusedWindow err: {err}"));
+            }
+        }));
+
+        instance
+    }
+
+    pub fn state(&self) -> FocusedWindow {
+        self.state.read().unwrap().clone()
+    }
+
+    fn start(&self) -> Result<(), Box<dyn Error>> {
+        let (connection, screen_num) = x11rb::connect(None)?;
+        let root = connection.setup().roots[screen_num].root;
+        let atoms = Atoms {
+            net_active_window: intern(&connection, "_NET_ACTIVE_WINDOW")?,
+            net_wm_name: intern(&connection, "_NET_WM_NAME")?,
+            utf8_string: intern(&connection, "UTF8_STRING")?,
+        };
+
+        // Root changes _NET_ACTIVE_WINDOW on focus, the window its title:
+        watch_properties(&connection, root)?;
+        let mut active = active_window(&connection, root, &atoms)?;
+        if let Some(window) = active {
+            let _ = watch_properties(&connection, window);
+        }
+        self.update(&connection, active, &atoms);
+
+        let shutdown = self.shutdown.token();
+        while !shutdown.is_cancelled() {
+            let event = match connection.poll_for_event()? {
+                Some(event) => event,
+                None => {
+                    thread::sleep(POLL_INTERVAL);
+                    continue;
+                }
+            };
+
+            let Event::PropertyNotify(event) = event else {
+                continue;
+            };
+
+            if event.window == root && event.atom == atoms.net_active_window {
+                // Previous window keeps our event mask, it is harmless:
+                active = match active_window(&connection, root, &atoms) {
+                    Ok(window) => window,
+                    Err(err) => {
+                        Log::warn(
+                            "focused_window",
+                            format!("TauriFocusedWindow active window warn: {err}"),
+                        );
+                        continue;
+                    }
+                };
+                if let Some(window) = active {
+                    // Window may be gone already, the next focus change fixes it:
+                    let _ = watch_properties(&connection, window);
+                }
+                self.update(&connection, active, &atoms);
+            } else if Some(event.window) == active
+                && (event.atom == atoms.net_wm_name || event.atom == u32::from(AtomEnum::WM_NAME))
+            {
+                self.update(&connection, active, &atoms);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn update(&self, connection: &RustConnection, window: Option<Window>, atoms: &Atoms) {
+        let focused = match window {
+            Some(window) => FocusedWindow {
+                title: window_title(connection, window, atoms),
+                app_id: window_class(connection, window),
+            },
+            None => FocusedWindow::default(),
+        };
+
+        let mut state = self.state.write().unwrap();
+        if *state == focused {
+            return;
+        }
+        *state = focused.clone();
+        drop(state);
+
+        let _ = Emit::emit(&self.app_handle, "focused_window", EMIT_EVENT, focused);
+    }
+}
+
+fn intern(connection: &RustConnection, name: &str) -> Result<Atom, Box<dyn Error>> {
+    Ok(connection
+        .intern_atom(false, name.as_bytes())?
+        .reply()?
+        .atom)
+}
+
+fn watch_properties(connection: &RustConnection, window: Window) -> Result<(), Box<dyn Error>> {
+    let attributes = ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE);
+    connection
+        .change_window_attributes(window, &attributes)?
+        .check()?;
+    Ok(())
+}
+
+// None without an EWMH window manager or when nothing is focused
+fn active_window(
+    connection: &RustConnection,
+    root: Window,
+    atoms: &Atoms,
+) -> Result<Option<Window>, Box<dyn Error>> {
+    let reply = connection
+        .get_property(false, root, atoms.net_active_window, AtomEnum::WINDOW, 0, 1)?
+        .reply()?;
+
+    Ok(reply
+        .value32()
+        .and_then(|mut v| v.next())
+        .filter(|window| *window != 0))
+}
+
+fn property_string(
+    connection: &RustConnection,
+    window: Window,
+    property: impl Into<Atom>,
+    kind: impl Into<Atom>,
+) -> Option<Vec<u8>> {
+    let reply = connection
+        .get_property(false, window, property, kind, 0, u32::MAX)
+        .ok()?
+        .reply()
+        .ok()?;
+
+    (!reply.value.is_empty()).then_some(reply.value)
+}
+
+// _NET_WM_NAME is UTF-8, legacy WM_NAME is used when it is missing
+fn window_title(connection: &RustConnection, window: Window, atoms: &Atoms) -> String {
+    property_string(connection, window, atoms.net_wm_name, atoms.utf8_string)
+        .or_else(|| property_string(connection, window, AtomEnum::WM_NAME, AtomEnum::STRING))
+        .map(|v| String::from_utf8_lossy(&v).into_owned())
+        .unwrap_or_default()
+}
+
+// WM_CLASS is "instance\0class\0", class is the stable app name
+fn window_class(connection: &RustConnection, window: Window) -> String {
+    let value = match property_string(connection, window, AtomEnum::WM_CLASS, AtomEnum::STRING) {
+        Some(value) => value,
+        None => return String::new(),
+    };
+
+    let mut parts = value
+        .split(|v| *v == 0)
+        .filter(|v| !v.is_empty())
+        .map(|v| String::from_utf8_lossy(v).into_owned());
+    let instance = parts.next().unwrap_or_default();
+    parts.next().unwrap_or(instance)
+}
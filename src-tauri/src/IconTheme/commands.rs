@@ -0,0 +1,8 @@
+use super::{icon_data_url, IconTheme};
+
+#[tauri::command]
+pub async fn resolve_icon(name: String, size: u32) -> Result<Option<String>, String> {
+    Ok(IconTheme::current()
+        .lookup(&name, size)
+        .and_then(|path| icon_data_url(&path)))
+}
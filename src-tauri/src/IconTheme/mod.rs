@@ -0,0 +1,375 @@
+pub mod commands;
+
+use base64::Engine;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+
+// Webview can't render xpm, so only these:
+const EXTENSIONS: [&str; 2] = ["png", "svg"];
+
+static CURRENT: Mutex<Option<Arc<IconTheme>>> = Mutex::new(None);
+
+// Icon lookup by the freedesktop icon theme spec, shared by tray and media
+pub struct IconTheme {
+    base_dirs: Vec<PathBuf>,
+    // Current theme first, then what it inherits, hicolor last:
+    themes: Vec<ThemeIndex>,
+    cache: Mutex<HashMap<(String, u32), Option<PathBuf>>>,
+}
+
+struct ThemeIndex {
+    name: String,
+    directories: Vec<ThemeDirectory>,
+}
+
+struct ThemeDirectory {
+    path: String,
+    size: u32,
+    scale: u32,
+    kind: String,
+    min_size: u32,
+    max_size: u32,
+    threshold: u32,
+}
+
+impl ThemeDirectory {
+    fn matches_size(&self, size: u32) -> bool {
+        match self.kind.as_str() {
+            "Fixed" => self.size * self.scale == size,
+            "Scalable" => self.min_size * self.scale <= size && size <= self.max_size * self.scale,
+            _ => {
+                self.size.saturating_sub(self.threshold) * self.scale <= size
+                    && size <= (self.size + self.threshold) * self.scale
+            }
+        }
+    }
+
+    fn size_distance(&self, size: u32) -> u32 {
+        let (min, max) = match self.kind.as_str() {
+            "Fixed" => (self.size, self.size),
+            "Scalable" => (self.min_size, self.max_size),
+            _ => (
+                self.size.saturating_sub(self.threshold),
+                self.size + self.threshold,
+            ),
+        };
+
+        if size < min * self.scale {
+            min * self.scale - size
+        } else {
+            size.saturating_sub(max * self.scale)
+        }
+    }
+}
+
+impl IconTheme {
+    // Theme of the running desktop, loaded on first use
+    pub fn current() -> Arc<IconTheme> {
+        let mut current = CURRENT.lock().unwrap();
+        current
+            .get_or_insert_with(|| Arc::new(IconTheme::load(&current_theme_name())))
+            .clone()
+    }
+
+    // Read theme again, after the user changed it
+    pub fn reload() -> Arc<IconTheme> {
+        let theme = Arc::new(IconTheme::load(&current_theme_name()));
+        *CURRENT.lock().unwrap() = Some(theme.clone());
+        theme
+    }
+
+    pub fn load(theme_name: &str) -> Self {
+        let base_dirs = base_dirs();
+        let mut themes: Vec<ThemeIndex> = Vec::new();
+        let mut pending = vec![theme_name.to_string()];
+
+        while let Some(name) = pending.pop() {
+            if themes.iter().any(|v| v.name == name) {
+                continue;
+            }
+
+            if let Some((theme, inherits)) = read_index(&base_dirs, &name) {
+                themes.push(theme);
+                // Keep order of Inherits, pending is a stack:
+                pending.extend(inherits.into_iter().rev());
+            }
+        }
+
+        // hicolor is always the last resort:
+        if let Some(position) = themes.iter().position(|v| v.name == "hicolor") {
+            let hicolor = themes.remove(position);
+            themes.push(hicolor);
+        } else if let Some((hicolor, _)) = read_index(&base_dirs, "hicolor") {
+            themes.push(hicolor);
+        }
+
+        Self {
+            base_dirs,
+            themes,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Path of the icon with name closest to size, name can be an absolute path too
+    pub fn lookup(&self, name: &str, size: u32) -> Option<PathBuf> {
+        if name.is_empty() {
+            return None;
+        }
+
+        if name.starts_with('/') {
+            let path = PathBuf::from(name);
+            return path.is_file().then_some(path);
+        }
+
+        // Names come from other apps, they must not point out of the theme dirs:
+        if !is_icon_name(name) {
+            return None;
+        }
+
+        let key = (name.to_string(), size);
+        if let Some(path) = self.cache.lock().unwrap().get(&key) {
+            return path.clone();
+        }
+
+        let path = self
+            .themes
+            .iter()
+            .find_map(|theme| self.lookup_in_theme(theme, name, size))
+            .or_else(|| self.lookup_fallback(name));
+
+        self.cache.lock().unwrap().insert(key, path.clone());
+        path
+    }
+
+    fn lookup_in_theme(&self, theme: &ThemeIndex, name: &str, size: u32) -> Option<PathBuf> {
+        let mut closest: Option<(u32, PathBuf)> = None;
+
+        for directory in &theme.directories {
+            for base_dir in &self.base_dirs {
+                let dir = base_dir.join(&theme.name).join(&directory.path);
+                let path = match find_with_extension(&dir, name) {
+                    Some(path) => path,
+                    None => continue,
+                };
+
+                if directory.matches_size(size) {
+                    return Some(path);
+                }
+
+                let distance = directory.size_distance(size);
+                if closest.as_ref().is_none_or(|(v, _)| distance < *v) {
+                    closest = Some((distance, path));
+                }
+            }
+        }
+
+        closest.map(|(_, path)| path)
+    }
+
+    fn lookup_fallback(&self, name: &str) -> Option<PathBuf> {
+        self.base_dirs
+            .iter()
+            .find_map(|dir| find_with_extension(dir, name))
+            .or_else(|| find_with_extension(Path::new("/usr/share/pixmaps"), name))
+    }
+}
+
+// Icon as data url, so the webview can load it without file access
+pub fn icon_data_url(path: &Path) -> Option<String> {
+    let mime = match path.extension().and_then(|v| v.to_str()) {
+        Some("png") => "image/png",
+        Some("svg") => "image/svg+xml",
+        _ => return None,
+    };
+
+    let bytes = fs::read(path).ok()?;
+    let data = base64::engine::general_purpose::STANDARD.encode(bytes);
+
+    Some(format!("data:{mime};base64,{data}"))
+}
+
+// Plain icon name, without path separators or parent references
+pub fn is_icon_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains('/') && !name.contains("..")
+}
+
+pub fn find_with_extension(dir: &Path, name: &str) -> Option<PathBuf> {
+    EXTENSIONS
+        .iter()
+        .map(|extension| dir.join(format!("{name}.{extension}")))
+        .find(|path| path.is_file())
+}
+
+fn base_dirs() -> Vec<PathBuf> {
+    let home = env::var("HOME").unwrap_or_default();
+    let data_home = env::var("XDG_DATA_HOME")
+        .ok()
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| format!("{home}/.local/share"));
+    let data_dirs = env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+
+    let mut dirs = vec![
+        PathBuf::from(format!("{home}/.icons")),
+        PathBuf::from(data_home).join("icons"),
+    ];
+    dirs.extend(data_dirs.split(':').map(|v| PathBuf::from(v).join("icons")));
+
+    dirs
+}
+
+// Parse index.theme of theme, also returns the themes it inherits
+fn read_index(base_dirs: &[PathBuf], name: &str) -> Option<(ThemeIndex, Vec<String>)> {
+    let content = base_dirs
+        .iter()
+        .find_map(|dir| fs::read_to_string(dir.join(name).join("index.theme")).ok())?;
+
+    let sections = parse_ini(&content);
+    let theme_section = sections.get("Icon Theme")?;
+
+    let inherits = theme_section
+        .get("Inherits")
+        .map(|v| v.split(',').map(|v| v.trim().to_string()).collect())
+        .unwrap_or_default();
+
+    let directories = theme_section
+        .get("Directories")
+        .map(|v| v.as_str())
+        .unwrap_or_default()
+        .split(',')
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .filter_map(|path| {
+            let section = sections.get(path)?;
+            let number = |key: &str| section.get(key).and_then(|v| v.parse::<u32>().ok());
+            let size = number("Size")?;
+
+            Some(ThemeDirectory {
+                path: path.to_string(),
+                size,
+                scale: number("Scale").unwrap_or(1),
+                kind: section
+                    .get("Type")
+                    .cloned()
+                    .unwrap_or_else(|| "Threshold".to_string()),
+                min_size: number("MinSize").unwrap_or(size),
+                max_size: number("MaxSize").unwrap_or(size),
+                threshold: number("Threshold").unwrap_or(2),
+            })
+        })
+        .collect();
+
+    Some((
+        ThemeIndex {
+            name: name.to_string(),
+            directories,
+        },
+        inherits,
+    ))
+}
+
+fn parse_ini(content: &str) -> HashMap<String, HashMap<String, String>> {
+    let mut sections: HashMap<String, HashMap<String, String>> = HashMap::new();
+    let mut current = String::new();
+
+    for line in content.lines().map(|v| v.trim()) {
+        if line.starts_with('#') || line.is_empty() {
+            continue;
+        }
+
+        if let Some(section) = line.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+            current = section.to_string();
+        } else if let Some((key, value)) = line.split_once('=') {
+            sections
+                .entry(current.clone())
+                .or_default()
+                .insert(key.trim().to_string(), value.trim().to_string());
+        }
+    }
+
+    sections
+}
+
+// KDE stores theme in kdeglobals, GTK in settings.ini or gsettings
+fn current_theme_name() -> String {
+    let home = env::var("HOME").unwrap_or_default();
+    let config_home = env::var("XDG_CONFIG_HOME")
+        .ok()
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| format!("{home}/.config"));
+
+    let is_kde = env::var("XDG_CURRENT_DESKTOP")
+        .map(|v| v.to_uppercase().contains("KDE"))
+        .unwrap_or_default();
+
+    let from_file = |file: &str, section: &str, key: &str| {
+        let content = fs::read_to_string(format!("{config_home}/{file}")).ok()?;
+        parse_ini(&content).get(section)?.get(key).cloned()
+    };
+
+    let from_gsettings = || {
+        let output = Command::new("gsettings")
+            .args(["get", "org.gnome.desktop.interface", "icon-theme"])
+            .output()
+            .ok()?;
+        let name = String::from_utf8_lossy(&output.stdout)
+            .trim()
+            .trim_matches('\'')
+            .to_string();
+        (!name.is_empty()).then_some(name)
+    };
+
+    let theme = if is_kde {
+        from_file("kdeglobals", "Icons", "Theme")
+    } else {
+        None
+    };
+
+    theme
+        .or_else(|| from_file("gtk-3.0/settings.ini", "Settings", "gtk-icon-theme-name"))
+        .or_else(from_gsettings)
+        .unwrap_or_else(|| "hicolor".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_icon_name, IconTheme};
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    #[test]
+    fn icon_names_are_plain() {
+        assert!(is_icon_name("nm-signal-75"));
+        assert!(is_icon_name("org.kde.konsole"));
+        assert!(!is_icon_name("../../../etc/passwd"));
+        assert!(!is_icon_name("icons/app"));
+        assert!(!is_icon_name("app..png"));
+        assert!(!is_icon_name(""));
+    }
+
+    #[test]
+    fn lookup_rejects_relative_paths() {
+        let dir = std::env::temp_dir().join("touri-icon-theme-test");
+        std::fs::create_dir_all(dir.join("icons")).unwrap();
+        std::fs::write(dir.join("secret.png"), b"png").unwrap();
+        let theme = IconTheme {
+            base_dirs: vec![dir.join("icons")],
+            themes: Vec::new(),
+            cache: Mutex::new(HashMap::new()),
+        };
+
+        assert_eq!(theme.lookup("../secret", 24), None);
+        // Absolute paths are still allowed:
+        let absolute = dir.join("secret.png");
+        assert_eq!(
+            theme.lookup(absolute.to_str().unwrap(), 24),
+            Some(absolute.clone())
+        );
+    }
+}
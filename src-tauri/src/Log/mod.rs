@@ -0,0 +1,54 @@
+use crate::Emit;
+use serde::Serialize;
+use std::fmt::Display;
+use std::sync::OnceLock;
+use tauri::{AppHandle, Wry};
+
+// Backend problem shown by the frontend, stderr is gone in a packaged app
+pub const EMIT_EVENT: &str = "onBackendError";
+
+// Set once on setup, until then (and in tests) messages only go to stderr
+static APP_HANDLE: OnceLock<AppHandle<Wry>> = OnceLock::new();
+
+#[derive(Clone, Copy, Serialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Error,
+    Warn,
+}
+
+#[derive(Clone, Serialize, Debug)]
+pub struct BackendError {
+    // Module key, same as for emit targets, e.g. "media_player":
+    subsystem: String,
+    level: Level,
+    message: String,
+}
+
+pub fn init(app_handle: AppHandle<Wry>) {
+    let _ = APP_HANDLE.set(app_handle);
+}
+
+pub fn error(subsystem: &str, message: impl Display) {
+    log(subsystem, Level::Error, message);
+}
+
+pub fn warn(subsystem: &str, message: impl Display) {
+    log(subsystem, Level::Warn, message);
+}
+
+// Message still goes to stderr as before, and to the module's emit target
+pub fn log(subsystem: &str, level: Level, message: impl Display) {
+    let message = message.to_string();
+    eprintln!("{message}");
+
+    let Some(app_handle) = APP_HANDLE.get() else {
+        return;
+    };
+    let payload = BackendError {
+        subsystem: subsystem.to_string(),
+        level,
+        message,
+    };
+    let _ = Emit::emit(app_handle, subsystem, EMIT_EVENT, payload);
+}
@@ -0,0 +1,148 @@
+use crate::Log;
+use base64::Engine;
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+
+// Larger files are not inlined, data: uris go over IPC with every update
+const MAX_INLINE_BYTES: u64 = 4 * 1024 * 1024;
+
+// Cover as a base64 data: uri, type is sniffed since cached art often
+// has no extension
+pub(super) fn data_url(path: &Path) -> Option<String> {
+    let size = fs::metadata(path).ok()?.len();
+    if size > MAX_INLINE_BYTES {
+        Log::warn(
+            "media_player",
+            format!(
+                "TauriMediaPlayer art err: {}: {size} bytes is too big to inline",
+                path.display()
+            ),
+        );
+        return None;
+    }
+
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            Log::warn(
+                "media_player",
+                format!("TauriMediaPlayer art err: {}: {err}", path.display()),
+            );
+            return None;
+        }
+    };
+    let mime = image::guess_format(&bytes)
+        .map(|format| format.to_mime_type())
+        .ok()?;
+    let data = base64::engine::general_purpose::STANDARD.encode(bytes);
+
+    Some(format!("data:{mime};base64,{data}"))
+}
+
+// Average color of the cover as "#rrggbb", for tinting the widget
+pub(super) fn average_color(path: &Path) -> Option<String> {
+    let image = match image::open(path) {
+        Ok(image) => image,
+        Err(err) => {
+            Log::warn(
+                "media_player",
+                format!("TauriMediaPlayer art err: {}: {err}", path.display()),
+            );
+            return None;
+        }
+    };
+
+    // Downsample first, few pixels are enough for an average:
+    let pixels = image.thumbnail(32, 32).to_rgb8();
+    let count = u64::from(pixels.width()) * u64::from(pixels.height());
+    if count == 0 {
+        return None;
+    }
+
+    let mut sum = [0u64; 3];
+    for pixel in pixels.pixels() {
+        for (channel, value) in sum.iter_mut().zip(pixel.0) {
+            *channel += u64::from(value);
+        }
+    }
+
+    Some(format!(
+        "#{:02x}{:02x}{:02x}",
+        sum[0] / count,
+        sum[1] / count,
+        sum[2] / count
+    ))
+}
+
+// Width and height from the image header, pixels aren't decoded
+pub(super) fn dimensions(path: &Path) -> Option<(u32, u32)> {
+    match image::image_dimensions(path) {
+        Ok(size) => Some(size),
+        Err(err) => {
+            Log::warn(
+                "media_player",
+                format!("TauriMediaPlayer art err: {}: {err}", path.display()),
+            );
+            None
+        }
+    }
+}
+
+// Same for base64 data: uris sent by players as artUrl
+pub(super) fn data_url_dimensions(data_url: &str) -> Option<(u32, u32)> {
+    let (header, data) = data_url.strip_prefix("data:")?.split_once(',')?;
+    if !header.ends_with(";base64") {
+        return None;
+    }
+
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .ok()?;
+    image::ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .ok()?
+        .into_dimensions()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{data_url_dimensions, dimensions};
+    use base64::Engine;
+    use std::io::Cursor;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut png = Cursor::new(Vec::new());
+        image::RgbaImage::new(width, height)
+            .write_to(&mut png, image::ImageFormat::Png)
+            .unwrap();
+        png.into_inner()
+    }
+
+    #[test]
+    fn file_dimensions() {
+        let path = std::env::temp_dir().join("opentopbar-art-dimensions.png");
+        std::fs::write(&path, png(3, 2)).unwrap();
+        assert_eq!(dimensions(&path), Some((3, 2)));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn missing_file_has_no_dimensions() {
+        assert_eq!(dimensions("/nonexistent/art.png".as_ref()), None);
+    }
+
+    #[test]
+    fn data_url_dimensions_from_header() {
+        let data = base64::engine::general_purpose::STANDARD.encode(png(5, 4));
+        let data_url = format!("data:image/png;base64,{data}");
+        assert_eq!(data_url_dimensions(&data_url), Some((5, 4)));
+    }
+
+    #[test]
+    fn data_url_without_base64_is_unknown() {
+        assert_eq!(data_url_dimensions("data:image/png,abc"), None);
+        assert_eq!(data_url_dimensions("https://example.com/a.png"), None);
+    }
+}
@@ -0,0 +1,230 @@
+use super::{
+    MediaMetrics, MediaPlayerConfig, MediaProgress, MediaSnapshotError, MediaStruct, PlayingPlayer,
+    StatusTransition, TauriMediaPlayer,
+};
+use serde_json::Value as JsonValue;
+use std::sync::Arc;
+use tauri::State;
+
+#[tauri::command]
+pub async fn media_pin_player(
+    bus_name: String,
+    media_player: State<'_, Arc<TauriMediaPlayer>>,
+) -> Result<(), String> {
+    media_player.pin_player(bus_name).await
+}
+
+#[tauri::command]
+pub async fn media_unpin(media_player: State<'_, Arc<TauriMediaPlayer>>) -> Result<(), String> {
+    media_player.unpin().await;
+    Ok(())
+}
+
+// None goes back to following the playing player
+#[tauri::command]
+pub async fn media_set_preferred_player(
+    bus_name: Option<String>,
+    media_player: State<'_, Arc<TauriMediaPlayer>>,
+) -> Result<(), String> {
+    match bus_name {
+        Some(bus_name) => media_player.pin_player(bus_name).await,
+        None => {
+            media_player.unpin().await;
+            Ok(())
+        }
+    }
+}
+
+#[tauri::command]
+pub async fn media_get_pinned_player(
+    media_player: State<'_, Arc<TauriMediaPlayer>>,
+) -> Result<Option<String>, String> {
+    Ok(media_player.pinned_player().await)
+}
+
+#[tauri::command]
+pub async fn media_get_config(
+    media_player: State<'_, Arc<TauriMediaPlayer>>,
+) -> Result<MediaPlayerConfig, String> {
+    Ok(media_player.config().await)
+}
+
+#[tauri::command]
+pub async fn media_set_config(
+    config: MediaPlayerConfig,
+    media_player: State<'_, Arc<TauriMediaPlayer>>,
+) -> Result<(), String> {
+    media_player.set_config(config).await;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn media_set_max_text_length(
+    max_length: usize,
+    media_player: State<'_, Arc<TauriMediaPlayer>>,
+) -> Result<(), String> {
+    media_player.set_max_text_length(max_length).await
+}
+
+#[tauri::command]
+pub async fn media_set_now_playing_template(
+    template: String,
+    media_player: State<'_, Arc<TauriMediaPlayer>>,
+) -> Result<(), String> {
+    media_player.set_now_playing_template(template).await
+}
+
+#[tauri::command]
+pub async fn media_get_players(
+    media_player: State<'_, Arc<TauriMediaPlayer>>,
+) -> Result<Vec<MediaStruct>, String> {
+    Ok(media_player.players().await)
+}
+
+#[tauri::command]
+pub async fn media_playing_players(
+    media_player: State<'_, Arc<TauriMediaPlayer>>,
+) -> Result<Vec<PlayingPlayer>, String> {
+    media_player.playing_players().await
+}
+
+#[tauri::command]
+pub async fn media_status_history(
+    bus_name: String,
+    media_player: State<'_, Arc<TauriMediaPlayer>>,
+) -> Result<Vec<StatusTransition>, String> {
+    Ok(media_player.status_history(&bus_name).await)
+}
+
+// Dev only: fake player state to test the frontend widget
+#[tauri::command]
+pub async fn media_inject(
+    media: MediaStruct,
+    media_player: State<'_, Arc<TauriMediaPlayer>>,
+) -> Result<(), String> {
+    if !cfg!(debug_assertions) {
+        return Err("media_inject is available in dev builds only".to_string());
+    }
+
+    media_player.inject(media)
+}
+
+#[tauri::command]
+pub async fn media_metrics(
+    media_player: State<'_, Arc<TauriMediaPlayer>>,
+) -> Result<MediaMetrics, String> {
+    Ok(media_player.metrics())
+}
+
+// Another application grabbing over us is sent as onMediaKeysConflict
+#[tauri::command]
+pub async fn media_keys_enable(
+    media_player: State<'_, Arc<TauriMediaPlayer>>,
+) -> Result<(), String> {
+    media_player.inner().clone().enable_media_keys().await
+}
+
+#[tauri::command]
+pub async fn media_keys_disable(
+    media_player: State<'_, Arc<TauriMediaPlayer>>,
+) -> Result<(), String> {
+    media_player.disable_media_keys().await
+}
+
+#[tauri::command]
+pub async fn media_get_position(
+    media_player: State<'_, Arc<TauriMediaPlayer>>,
+) -> Result<i64, String> {
+    media_player.position().await
+}
+
+// Open the player app from the widget
+#[tauri::command]
+pub async fn media_raise(media_player: State<'_, Arc<TauriMediaPlayer>>) -> Result<(), String> {
+    media_player.raise().await
+}
+
+#[tauri::command]
+pub async fn media_play_pause(
+    media_player: State<'_, Arc<TauriMediaPlayer>>,
+) -> Result<(), String> {
+    media_player.control("PlayPause").await
+}
+
+#[tauri::command]
+pub async fn media_play(media_player: State<'_, Arc<TauriMediaPlayer>>) -> Result<(), String> {
+    media_player.control("Play").await
+}
+
+#[tauri::command]
+pub async fn media_pause(media_player: State<'_, Arc<TauriMediaPlayer>>) -> Result<(), String> {
+    media_player.control("Pause").await
+}
+
+#[tauri::command]
+pub async fn media_stop(media_player: State<'_, Arc<TauriMediaPlayer>>) -> Result<(), String> {
+    media_player.control("Stop").await
+}
+
+#[tauri::command]
+pub async fn media_next(media_player: State<'_, Arc<TauriMediaPlayer>>) -> Result<(), String> {
+    media_player.control("Next").await
+}
+
+#[tauri::command]
+pub async fn media_previous(media_player: State<'_, Arc<TauriMediaPlayer>>) -> Result<(), String> {
+    media_player.control("Previous").await
+}
+
+#[tauri::command]
+pub async fn media_seek(
+    position_us: i64,
+    media_player: State<'_, Arc<TauriMediaPlayer>>,
+) -> Result<(), String> {
+    media_player.seek(position_us).await
+}
+
+#[tauri::command]
+pub async fn media_set_volume(
+    value: f64,
+    media_player: State<'_, Arc<TauriMediaPlayer>>,
+) -> Result<(), String> {
+    media_player.set_volume(value).await
+}
+
+#[tauri::command]
+pub async fn media_set_rate(
+    value: f64,
+    media_player: State<'_, Arc<TauriMediaPlayer>>,
+) -> Result<(), String> {
+    media_player.set_rate(value).await
+}
+
+#[tauri::command]
+pub async fn media_toggle_shuffle(
+    media_player: State<'_, Arc<TauriMediaPlayer>>,
+) -> Result<(), String> {
+    media_player.toggle_shuffle().await
+}
+
+#[tauri::command]
+pub async fn media_cycle_loop(
+    media_player: State<'_, Arc<TauriMediaPlayer>>,
+) -> Result<(), String> {
+    media_player.cycle_loop().await
+}
+
+#[tauri::command]
+pub async fn media_progress(
+    media_player: State<'_, Arc<TauriMediaPlayer>>,
+) -> Result<MediaProgress, String> {
+    media_player.progress().await
+}
+
+#[tauri::command]
+pub async fn media_full_snapshot(
+    bus_name: String,
+    media_player: State<'_, Arc<TauriMediaPlayer>>,
+) -> Result<JsonValue, MediaSnapshotError> {
+    media_player.full_snapshot(&bus_name).await
+}
@@ -0,0 +1,89 @@
+use super::{LengthUnit, DEBOUNCE_MS};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct MediaPlayerConfig {
+    // Keep last N PlaybackStatus transitions per player, 0 disables it:
+    pub status_history_size: usize,
+    // Switch to another player only after it was the sole Playing one
+    // for this long, 0 switches on any update:
+    pub switch_delay_ms: u64,
+    // Count received/emitted updates and status latency (media_metrics):
+    pub metrics: bool,
+    // Compatibility shim: non-standard keys some buggy players put artist under,
+    // checked in order only when xesam:artist is missing or empty
+    pub artist_fallback_keys: Vec<String>,
+    // Skip emits while the toolbar window is hidden, the latest state
+    // is sent once it is shown again:
+    pub emit_only_when_visible: bool,
+    // Truncate title, artist and album to N graphemes with an ellipsis,
+    // full values stay in *_full fields, 0 disables it:
+    pub max_text_length: usize,
+    // Glob patterns on player bus names, e.g. "org.mpris.MediaPlayer2.firefox.*",
+    // empty allow list allows all. A pinned player ignores them:
+    pub player_allow: Vec<String>,
+    pub player_deny: Vec<String>,
+    // Drop repeated artists of xesam:artist, raw list is kept in artist_raw:
+    pub dedup_artists: bool,
+    // Strip control characters (but tab and newlines) from title, artist and
+    // album, values as sent are kept in *_raw fields:
+    pub strip_control_chars: bool,
+    // Debounce of status, metadata and position-only updates, used as
+    // given on start:
+    pub status_debounce_ms: u64,
+    pub metadata_debounce_ms: u64,
+    pub position_debounce_ms: u64,
+    // Also send local (file://) art as a base64 data: uri in art_data_url:
+    pub inline_art: bool,
+    // now_playing_line format, with {title}, {artist}, {album} and {status}:
+    pub now_playing_template: String,
+    // Thresholds of the transient hint, 0 disables the signal:
+    pub transient_max_length_ms: u64,
+    pub transient_stop_ms: u64,
+    // Send onUpdateMediaMeta as an object instead of a JSON string. Off
+    // keeps the old contract, frontends parsing the string must switch first
+    pub structured_payload: bool,
+    // Compatibility shim for players sending mpris:length in seconds ("s")
+    // or milliseconds ("ms") instead of microseconds, by well-known bus
+    // name, e.g. {"org.mpris.MediaPlayer2.foo": "ms"}:
+    pub length_units: HashMap<String, LengthUnit>,
+    // Same shim without a per-player unit: guess it when length is under a
+    // second but position ran past it. Can misfire early in a track, off
+    // by default:
+    pub guess_length_unit: bool,
+}
+
+impl Default for MediaPlayerConfig {
+    fn default() -> Self {
+        Self {
+            status_history_size: 0,
+            switch_delay_ms: 0,
+            metrics: false,
+            artist_fallback_keys: vec![
+                "mpris:artist".to_string(),
+                "xesam:albumArtist".to_string(),
+                "xesam:albumartist".to_string(),
+                "albumartist".to_string(),
+                "artist".to_string(),
+            ],
+            emit_only_when_visible: false,
+            max_text_length: 0,
+            player_allow: Vec::new(),
+            player_deny: Vec::new(),
+            dedup_artists: false,
+            strip_control_chars: false,
+            status_debounce_ms: 20,
+            metadata_debounce_ms: DEBOUNCE_MS,
+            position_debounce_ms: 1000,
+            inline_art: false,
+            now_playing_template: "{artist} — {title}".to_string(),
+            transient_max_length_ms: 5000,
+            transient_stop_ms: 3000,
+            structured_payload: false,
+            length_units: HashMap::new(),
+            guess_length_unit: false,
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use super::MediaPlayerConfig;
+use crate::Log;
+use glob::Pattern;
+
+// Allow/deny globs for player bus names, compiled once per config
+#[derive(Default)]
+pub(super) struct PlayerFilter {
+    allow: Vec<Pattern>,
+    deny: Vec<Pattern>,
+    // Patterns that aren't valid globs, they match nothing:
+    invalid: Vec<String>,
+}
+
+impl PlayerFilter {
+    pub(super) fn new(config: &MediaPlayerConfig) -> Self {
+        let mut invalid = Vec::new();
+        let filter = Self {
+            allow: compile(&config.player_allow, &mut invalid),
+            deny: compile(&config.player_deny, &mut invalid),
+            invalid,
+        };
+        for err in &filter.invalid {
+            Log::warn(
+                "media_player",
+                format!("TauriMediaPlayer filter err: {err}"),
+            );
+        }
+
+        filter
+    }
+
+    // Empty allow list allows every player not denied
+    pub(super) fn allows(&self, bus_name: &str) -> bool {
+        let allowed = self.allow.is_empty() || self.allow.iter().any(|v| v.matches(bus_name));
+        allowed && !self.deny.iter().any(|v| v.matches(bus_name))
+    }
+}
+
+// Invalid patterns are left out, with why in invalid
+fn compile(patterns: &[String], invalid: &mut Vec<String>) -> Vec<Pattern> {
+    patterns
+        .iter()
+        .filter_map(|pattern| match Pattern::new(pattern) {
+            Ok(pattern) => Some(pattern),
+            Err(err) => {
+                invalid.push(format!("{pattern}: {err}"));
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MediaPlayerConfig, PlayerFilter};
+
+    const FIREFOX: &str = "org.mpris.MediaPlayer2.firefox.instance_1_42";
+    const FLATPAK_FIREFOX: &str = "org.mpris.MediaPlayer2.org.mozilla.firefox.instance_2_7";
+    const SPOTIFY: &str = "org.mpris.MediaPlayer2.spotify";
+
+    fn filter(allow: &[&str], deny: &[&str]) -> PlayerFilter {
+        PlayerFilter::new(&MediaPlayerConfig {
+            player_allow: allow.iter().map(|v| v.to_string()).collect(),
+            player_deny: deny.iter().map(|v| v.to_string()).collect(),
+            ..MediaPlayerConfig::default()
+        })
+    }
+
+    #[test]
+    fn empty_lists_allow_all() {
+        let filter = filter(&[], &[]);
+        assert!(filter.allows(FIREFOX));
+        assert!(filter.allows(SPOTIFY));
+    }
+
+    #[test]
+    fn allow_only() {
+        let filter = filter(&["org.mpris.MediaPlayer2.spotify*"], &[]);
+        assert!(filter.allows(SPOTIFY));
+        assert!(!filter.allows(FIREFOX));
+    }
+
+    #[test]
+    fn deny_only() {
+        let filter = filter(&[], &["org.mpris.MediaPlayer2.org.mozilla.*"]);
+        assert!(!filter.allows(FLATPAK_FIREFOX));
+        assert!(filter.allows(FIREFOX));
+        assert!(filter.allows(SPOTIFY));
+    }
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let filter = filter(&["org.mpris.MediaPlayer2.*"], &["*firefox*"]);
+        assert!(filter.allows(SPOTIFY));
+        assert!(!filter.allows(FIREFOX));
+        assert!(!filter.allows(FLATPAK_FIREFOX));
+    }
+
+    #[test]
+    fn invalid_glob_is_skipped() {
+        let filter = filter(&["[unclosed", "*spotify"], &["***"]);
+        assert_eq!(filter.invalid.len(), 2);
+        assert!(filter.invalid[0].starts_with("[unclosed: "));
+        // Valid ones still apply, the broken deny denies nothing:
+        assert!(filter.allows(SPOTIFY));
+        assert!(!filter.allows(FIREFOX));
+    }
+}
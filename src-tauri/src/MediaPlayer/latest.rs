@@ -0,0 +1,126 @@
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+// Channel of one slot holding the newest value: a lagging receiver gets the
+// latest state instead of updates being dropped on a full buffer
+pub(super) fn channel<T>() -> (LatestSender<T>, LatestReceiver<T>) {
+    let shared = Arc::new(Shared {
+        slot: Mutex::new(Slot {
+            value: None,
+            closed: false,
+            coalesced: 0,
+        }),
+        notify: Notify::new(),
+    });
+
+    (
+        LatestSender {
+            shared: shared.clone(),
+        },
+        LatestReceiver { shared },
+    )
+}
+
+struct Shared<T> {
+    slot: Mutex<Slot<T>>,
+    notify: Notify,
+}
+
+struct Slot<T> {
+    value: Option<T>,
+    // Receiver is dropped, nothing will take values anymore:
+    closed: bool,
+    // Values replaced before they were taken:
+    coalesced: u64,
+}
+
+pub(super) struct LatestSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+pub(super) struct LatestReceiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Clone for LatestSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> LatestSender<T> {
+    // Replaces the value not taken yet, only a closed channel fails
+    pub(super) fn send(&self, value: T) -> Result<(), String> {
+        let mut slot = self.shared.slot.lock().unwrap();
+        if slot.closed {
+            return Err("receiver is gone".to_string());
+        }
+
+        if slot.value.replace(value).is_some() {
+            slot.coalesced += 1;
+        }
+        drop(slot);
+
+        self.shared.notify.notify_one();
+        Ok(())
+    }
+
+    pub(super) fn coalesced(&self) -> u64 {
+        self.shared.slot.lock().unwrap().coalesced
+    }
+}
+
+impl<T> LatestReceiver<T> {
+    // Cancel safe, a value stays in the slot until it is returned
+    pub(super) async fn recv(&mut self) -> T {
+        loop {
+            if let Some(value) = self.shared.slot.lock().unwrap().value.take() {
+                return value;
+            }
+            self.shared.notify.notified().await;
+        }
+    }
+}
+
+impl<T> Drop for LatestReceiver<T> {
+    fn drop(&mut self) {
+        self.shared.slot.lock().unwrap().closed = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::channel;
+
+    #[tokio::test]
+    async fn flood_keeps_latest() {
+        let (sender, mut receiver) = channel();
+        for value in 0..1000 {
+            sender.send(value).unwrap();
+        }
+
+        assert_eq!(receiver.recv().await, 999);
+        assert_eq!(sender.coalesced(), 999);
+    }
+
+    #[tokio::test]
+    async fn taken_value_is_not_coalesced() {
+        let (sender, mut receiver) = channel();
+        sender.send(1).unwrap();
+        assert_eq!(receiver.recv().await, 1);
+        sender.send(2).unwrap();
+
+        assert_eq!(receiver.recv().await, 2);
+        assert_eq!(sender.coalesced(), 0);
+    }
+
+    #[test]
+    fn send_fails_without_receiver() {
+        let (sender, receiver) = channel();
+        drop(receiver);
+
+        assert!(sender.send(1).is_err());
+    }
+}
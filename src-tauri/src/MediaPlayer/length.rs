@@ -0,0 +1,80 @@
+use serde::{Deserialize, Serialize};
+
+// mpris:length is in microseconds by the spec, a few players send these
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LengthUnit {
+    S,
+    Ms,
+    Us,
+}
+
+impl LengthUnit {
+    fn scale(self) -> i64 {
+        match self {
+            LengthUnit::S => 1_000_000,
+            LengthUnit::Ms => 1_000,
+            LengthUnit::Us => 1,
+        }
+    }
+}
+
+// Shortest length still taken as microseconds by the guess:
+const PLAUSIBLE_LENGTH_US: i64 = 1_000_000;
+
+// Compatibility shim, see length_units and guess_length_unit: the player's
+// configured unit wins. The guess only fires for lengths under a second
+// that position already ran past, and picks the smallest unit covering it
+pub fn length_us(length: i64, position_us: i64, unit: Option<LengthUnit>, guess: bool) -> i64 {
+    if let Some(unit) = unit {
+        return length.saturating_mul(unit.scale());
+    }
+    if !guess || length <= 0 || length >= PLAUSIBLE_LENGTH_US || position_us <= length {
+        return length;
+    }
+
+    [LengthUnit::Ms, LengthUnit::S]
+        .into_iter()
+        .map(|unit| length.saturating_mul(unit.scale()))
+        .find(|length| *length >= position_us)
+        .unwrap_or(length)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{length_us, LengthUnit};
+
+    #[test]
+    fn configured_unit_wins() {
+        assert_eq!(length_us(180, 0, Some(LengthUnit::S), false), 180_000_000);
+        assert_eq!(
+            length_us(180_000, 0, Some(LengthUnit::Ms), true),
+            180_000_000
+        );
+        assert_eq!(length_us(500, 10_000_000, Some(LengthUnit::Us), true), 500);
+        assert_eq!(length_us(i64::MAX, 0, Some(LengthUnit::S), false), i64::MAX);
+    }
+
+    #[test]
+    fn untouched_without_opt_in() {
+        assert_eq!(length_us(180, 5_000_000, None, false), 180);
+    }
+
+    #[test]
+    fn guess_scales_short_lengths_position_ran_past() {
+        // Seconds: 180 * 1000 is still behind position
+        assert_eq!(length_us(180, 5_000_000, None, true), 180_000_000);
+        // Milliseconds:
+        assert_eq!(length_us(180_000, 5_000_000, None, true), 180_000_000);
+    }
+
+    #[test]
+    fn guess_keeps_plausible_lengths() {
+        assert_eq!(length_us(3_000_000, 5_000_000, None, true), 3_000_000);
+        // Short sound not played past its end:
+        assert_eq!(length_us(500_000, 200_000, None, true), 500_000);
+        assert_eq!(length_us(0, 5_000_000, None, true), 0);
+        // Position beyond any scale, nothing fits:
+        assert_eq!(length_us(2, 5_000_000, None, true), 2);
+    }
+}
@@ -0,0 +1,155 @@
+use super::TauriMediaPlayer;
+use crate::Emit;
+use crate::Log;
+use futures_util::StreamExt;
+use std::collections::HashSet;
+use std::sync::Arc;
+use zbus::dbus_proxy;
+use zbus::fdo::DBusProxy;
+use zbus::names::BusName;
+
+// Application name used for the media keys grab:
+const APP_NAME: &str = "opentopbar";
+
+// Media keys of GNOME settings daemon, keys go to the latest grabber
+// instead of its own MPRIS handling. KDE routes media keys itself.
+#[dbus_proxy(
+    interface = "org.gnome.SettingsDaemon.MediaKeys",
+    default_service = "org.gnome.SettingsDaemon.MediaKeys",
+    default_path = "/org/gnome/SettingsDaemon/MediaKeys"
+)]
+trait MediaKeys {
+    fn grab_media_player_keys(&self, application: &str, time: u32) -> zbus::Result<()>;
+
+    fn release_media_player_keys(&self, application: &str) -> zbus::Result<()>;
+
+    #[dbus_proxy(signal)]
+    fn media_player_key_pressed(&self, application: &str, key: &str) -> zbus::Result<()>;
+}
+
+pub(super) async fn grab(media_player: Arc<TauriMediaPlayer>) -> Result<(), String> {
+    let connection = media_player.connection().await?;
+
+    // No settings daemon, no keys to grab:
+    let dbus_proxy = DBusProxy::new(&connection)
+        .await
+        .map_err(|e| e.to_string())?;
+    let media_keys_name =
+        BusName::try_from("org.gnome.SettingsDaemon.MediaKeys").map_err(|e| e.to_string())?;
+    if !dbus_proxy
+        .name_has_owner(media_keys_name)
+        .await
+        .unwrap_or_default()
+    {
+        return Err("Media keys service is not available".to_string());
+    }
+
+    let media_keys = MediaKeysProxy::new(&connection)
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut key_pressed = media_keys
+        .receive_media_player_key_pressed()
+        .await
+        .map_err(|e| e.to_string())?;
+    media_keys
+        .grab_media_player_keys(APP_NAME, 0)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    // The daemon signals every listener with the application holding the
+    // keys, another name after our grab means it grabbed over us
+    let mut conflicts = HashSet::new();
+    let shutdown = media_player.shutdown.token();
+    while let Some(signal) = tokio::select! {
+        signal = key_pressed.next() => signal,
+        _ = shutdown.cancelled() => None,
+    } {
+        let args = match signal.args() {
+            Ok(args) => args,
+            Err(_) => continue,
+        };
+
+        if let Some(application) = conflict(&mut conflicts, args.application()) {
+            Log::warn(
+                "media_player",
+                format!("TauriMediaPlayer media keys taken by {application}"),
+            );
+            let _ = Emit::emit(
+                &media_player.app_handle,
+                "media_player",
+                "onMediaKeysConflict",
+                application,
+            );
+        }
+        if *args.application() != APP_NAME {
+            continue;
+        }
+
+        let key = args.key().to_string();
+        let _ = Emit::emit(
+            &media_player.app_handle,
+            "media_player",
+            "onMediaKeyPressed",
+            key.clone(),
+        );
+
+        let method = match key.as_str() {
+            "Play" => "PlayPause",
+            "Pause" => "Pause",
+            "Stop" => "Stop",
+            "Next" => "Next",
+            "Previous" => "Previous",
+            _ => continue,
+        };
+
+        if let Err(err) = media_player.control(method).await {
+            Log::error(
+                "media_player",
+                format!("TauriMediaPlayer media key err: {err}"),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+// Other grabber, reported once per application:
+fn conflict(reported: &mut HashSet<String>, application: &str) -> Option<String> {
+    if application == APP_NAME || !reported.insert(application.to_string()) {
+        return None;
+    }
+    Some(application.to_string())
+}
+
+pub(super) async fn release(media_player: &TauriMediaPlayer) -> Result<(), String> {
+    let connection = media_player.connection().await?;
+    let media_keys = MediaKeysProxy::new(&connection)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    media_keys
+        .release_media_player_keys(APP_NAME)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn other_grabber_is_reported_once() {
+        let mut reported = HashSet::new();
+
+        assert_eq!(conflict(&mut reported, APP_NAME), None);
+        assert_eq!(
+            conflict(&mut reported, "org.gnome.Music"),
+            Some("org.gnome.Music".to_string())
+        );
+        assert_eq!(conflict(&mut reported, "org.gnome.Music"), None);
+        assert_eq!(
+            conflict(&mut reported, "Rhythmbox"),
+            Some("Rhythmbox".to_string())
+        );
+    }
+}
@@ -0,0 +1,62 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// Latencies kept for the rolling average:
+const LATENCY_SAMPLES: usize = 50;
+
+#[derive(Clone, Serialize, Debug, Default)]
+pub struct MediaMetrics {
+    // PropertiesChanged messages of players:
+    received: u64,
+    // Updates sent to frontend, the rest were coalesced:
+    emitted: u64,
+    // Updates replaced by a newer one before the emit loop took them:
+    coalesced: u64,
+    status_latency_avg_ms: f64,
+}
+
+#[derive(Default)]
+pub(super) struct MetricsRecorder {
+    received: u64,
+    emitted: u64,
+    // When last not yet emitted status arrived:
+    pending_status: Option<Instant>,
+    latencies: VecDeque<Duration>,
+}
+
+impl MetricsRecorder {
+    pub fn record_received(&mut self, has_status: bool) {
+        self.received += 1;
+        if has_status {
+            self.pending_status = Some(Instant::now());
+        }
+    }
+
+    pub fn record_emitted(&mut self) {
+        self.emitted += 1;
+        if let Some(received_at) = self.pending_status.take() {
+            self.latencies.push_back(received_at.elapsed());
+            while self.latencies.len() > LATENCY_SAMPLES {
+                self.latencies.pop_front();
+            }
+        }
+    }
+
+    pub fn snapshot(&self, coalesced: u64) -> MediaMetrics {
+        let status_latency_avg_ms = match self.latencies.len() {
+            0 => 0.0,
+            count => {
+                let total: Duration = self.latencies.iter().sum();
+                total.as_secs_f64() * 1000.0 / count as f64
+            }
+        };
+
+        MediaMetrics {
+            received: self.received,
+            emitted: self.emitted,
+            coalesced,
+            status_latency_avg_ms,
+        }
+    }
+}
@@ -1,178 +1,2883 @@
+mod art;
+pub mod commands;
+mod config;
+mod filter;
+mod latest;
+mod length;
+mod media_keys;
+mod metrics;
+mod template;
+
+pub use config::MediaPlayerConfig;
+pub use length::LengthUnit;
+pub use metrics::MediaMetrics;
+
+use crate::Bus::{Backoff, BusConfig, ReconnectPolicy, SharedBus};
+use crate::DBusBridge::value_to_json;
+use crate::Emit;
+use crate::Log;
+use crate::Shutdown::Shutdown;
 use debounced::Debounced;
-use futures_channel::mpsc::{self, Sender};
-use futures_util::StreamExt;
-use serde::Serialize;
-use std::sync::Arc;
-use std::thread;
-use std::time::Duration;
-use tauri::{AppHandle, Emitter, Wry};
-use zbus::fdo::DBusProxy;
-use zbus::{Connection, MessageStream};
-use zvariant::Value;
+use futures_channel::mpsc;
+use futures_util::{Stream, StreamExt};
+use latest::LatestSender;
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map as JsonMap, Value as JsonValue};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Manager, Wry};
+use tokio::sync::{Notify, RwLock};
+use tokio_util::sync::CancellationToken;
+use unicode_segmentation::UnicodeSegmentation;
+use zbus::fdo::{DBusProxy, PropertiesProxy};
+use zbus::names::{BusName, InterfaceName};
+use zbus::{Connection, MatchRule, MessageStream, MessageType};
+use zvariant::{Dict, DynamicType, ObjectPath, OwnedValue, Signature, Value};
+
+// Default metadata debounce, then updates go to frontend with this event:
+pub const DEBOUNCE_MS: u64 = 100;
+// Position isn't signalled while it moves, it is polled when Playing
+const POSITION_POLL_MS: u64 = 1000;
+// How often a hidden window is checked while an update waits for it:
+const VISIBILITY_POLL_MS: u64 = 500;
+pub const EMIT_EVENT: &str = "onUpdateMediaMeta";
+// Every running player with its own state, on any change of one:
+pub const PLAYERS_EVENT: &str = "onUpdateMediaPlayers";
+// Pinned bus name, or null when the active player is followed:
+pub const PINNED_EVENT: &str = "onMediaPinnedPlayer";
 
 pub struct TauriMediaPlayer {
     app_handle: AppHandle<Wry>,
+    emit_sender: Mutex<Option<LatestSender<MediaStruct>>>,
+    connection: RwLock<Option<Connection>>,
+    config: RwLock<MediaPlayerConfig>,
+    player_filter: RwLock<filter::PlayerFilter>,
+    // Unique name -> player info, bus name is org.mpris.MediaPlayer2.*:
+    players: RwLock<HashMap<String, PlayerInfo>>,
+    pinned_player: RwLock<Option<PinnedPlayer>>,
+    // Unique name of player shown in the widget, and its state:
+    active_player: RwLock<Option<String>>,
+    media_info: RwLock<MediaStruct>,
+    // State of every allowed player by unique name, not just the active one:
+    player_states: RwLock<HashMap<String, MediaStruct>>,
+    status_history: RwLock<HashMap<String, VecDeque<StatusTransition>>>,
+    metrics: Mutex<metrics::MetricsRecorder>,
+    media_keys: Mutex<Option<tokio::task::JoinHandle<()>>>,
+    // Computed art colors by art url:
+    art_colors: Mutex<HashMap<String, Option<String>>>,
+    // Last inlined art by art_url, covers are too big to keep many:
+    art_data_url: Mutex<Option<(String, Option<String>)>>,
+    // Last art size by art_url:
+    art_size: Mutex<Option<(String, Option<ArtSize>)>>,
+    // Latest update skipped while the window was hidden:
+    suppressed: Mutex<Option<MediaStruct>>,
+    bus: BusConfig,
+    shared_bus: Arc<SharedBus>,
+    reconnect: ReconnectPolicy,
+    // Reconnect policy gave up, until restart is notified:
+    unavailable: RwLock<bool>,
+    restart: Notify,
+    shutdown: Arc<Shutdown>,
 }
 
-#[derive(Clone, Serialize, Debug)]
-struct MediaStruct {
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct MediaStruct {
+    // Well-known name of the player, and its instance suffix if any:
+    bus_name: String,
+    instance: String,
+    // Identity and DesktopEntry of the player, e.g. "Chromium" and
+    // "chromium" (for the app icon), empty when not exposed:
+    player_name: String,
+    desktop_entry: String,
     title: String,
     artist: Vec<String>,
     album: String,
+    // Untruncated values, for tooltips (see max_text_length):
+    title_full: String,
+    artist_full: Vec<String>,
+    album_full: String,
+    // Values as sent by player, only filled when dedup_artists (artist)
+    // or strip_control_chars is on:
+    title_raw: String,
+    artist_raw: Vec<String>,
+    album_raw: String,
+    // mpris:artUrl as sent, and decoded local path for file:// urls:
+    art_url: String,
+    art_path: Option<String>,
+    art_source: ArtSource,
+    // Local art inlined as a data: uri when inline_art is on, the webview
+    // can't load file:// urls:
+    art_data_url: Option<String>,
+    // Average color of local art, None without art:
+    art_color: Option<String>,
+    // Size from the header of local or data: art, None when unknown. Lets
+    // frontend reserve the space before the image loads:
+    art_width: Option<u32>,
+    art_height: Option<u32>,
+    status: String,
+    // Single line from now_playing_template, for narrow panels:
+    now_playing_line: String,
+    // Any metadata at all, streams may have only an url:
+    has_metadata: bool,
+    // A player is selected, even when it is paused without metadata:
+    has_player: bool,
+    // mpris:trackid changed while Playing (gapless next track), reset
+    // by the next metadata update:
+    track_changed: bool,
+    // Likely a notification or ad sound, not a track (see is_transient):
+    transient: bool,
+    // Some player is Playing, the selected one or not:
+    any_playing: bool,
+    // Stream quality, reported by some (radio) players:
+    audio_bitrate: Option<i64>,
+    audio_bpm: Option<i64>,
+    // Player Volume, 0.0 to 1.0:
+    volume: f64,
+    // Shuffle and LoopStatus ("None", "Track" or "Playlist"), left default
+    // when the player doesn't have them:
+    shuffle: bool,
+    loop_status: String,
+    // mpris:trackid of the track, needed to seek in it:
+    track_id: String,
+    // Track length from mpris:length and last known Position, 0 if unknown:
+    length_us: i64,
+    position_us: i64,
+    // mpris:length as sent, differs from length_us only when the unit
+    // shim scaled it:
+    length_raw: i64,
+    // Playback speed, 1.5 moves position 1.5 times faster than the clock:
+    rate: PlaybackRate,
+    capabilities: MediaCapabilities,
+}
+
+// Rate of the player, 1.0 when it doesn't have one
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(transparent)]
+struct PlaybackRate(f64);
+
+impl Default for PlaybackRate {
+    fn default() -> Self {
+        Self(1.0)
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct MediaCapabilities {
+    // What OpenUri of the player accepts, for drag and drop:
+    supported_uri_schemes: Vec<String>,
+    supported_mime_types: Vec<String>,
+    // Player interface Can* properties, for button states:
+    can_go_next: bool,
+    can_go_previous: bool,
+    can_play: bool,
+    can_pause: bool,
+    can_seek: bool,
+    can_control: bool,
+    // Root interface CanRaise, the player window can be brought to front:
+    can_raise: bool,
+}
+
+// Width and height of art
+type ArtSize = (u32, u32);
+
+// Where art comes from, so frontend can pick how to load it
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ArtSource {
+    #[default]
+    None,
+    File,
+    Data,
+    Remote,
+}
+
+impl ArtSource {
+    fn from_url(art_url: &str) -> Self {
+        match art_url.split_once(':').map(|(scheme, _)| scheme) {
+            _ if art_url.is_empty() => ArtSource::None,
+            Some("file") => ArtSource::File,
+            Some("data") => ArtSource::Data,
+            Some(_) => ArtSource::Remote,
+            // Some players send a plain path:
+            None if art_url.starts_with('/') => ArtSource::File,
+            None => ArtSource::None,
+        }
+    }
+}
+
+// Payload of onMediaCapabilities, sent apart from metadata
+#[derive(Clone, Serialize, Debug)]
+struct MediaCapabilitiesEvent {
+    bus_name: String,
+    capabilities: MediaCapabilities,
+}
+
+// Root interface properties, they don't change while the player lives
+#[derive(Clone, Debug, Default)]
+struct PlayerInfo {
+    bus_name: String,
+    identity: String,
+    desktop_entry: String,
+    supported_uri_schemes: Vec<String>,
+    supported_mime_types: Vec<String>,
+    can_raise: bool,
+}
+
+impl MediaStruct {
+    fn cleared() -> Self {
+        Self {
+            status: "Stopped".to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+// Playing player for a mixer view, volume is omitted if not exposed
+#[derive(Clone, Serialize, Debug)]
+pub struct PlayingPlayer {
+    bus_name: String,
+    identity: String,
+    title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    volume: Option<f64>,
+}
+
+// Position with what is needed to interpolate it, read in one GetAll
+#[derive(Clone, Serialize, Debug)]
+pub struct MediaProgress {
+    position_us: i64,
+    length_us: i64,
+    rate: f64,
+    status: String,
+    // Track the values belong to, to tell a track change during the read:
+    track_id: String,
+    // When it was read, position moves by rate from here:
+    timestamp_ms: u128,
+}
+
+// Why a snapshot failed, so the inspector can tell a closed player apart
+#[derive(Clone, Serialize, Debug)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum MediaSnapshotError {
+    NotStarted(String),
+    PlayerGone(String),
+    Failed(String),
+}
+
+#[derive(Clone, Serialize, Debug)]
+pub struct StatusTransition {
     status: String,
+    timestamp_ms: u128,
+}
+
+struct PinnedPlayer {
+    bus_name: String,
+    // Unique name owning bus_name, None while the player is gone:
+    owner: Option<String>,
+    resolved: bool,
 }
 
 impl TauriMediaPlayer {
-    pub fn new(app_handle: AppHandle<Wry>) -> Arc<Self> {
-        let instance = Arc::new(Self { app_handle });
+    pub fn new(
+        app_handle: AppHandle<Wry>,
+        shutdown: Arc<Shutdown>,
+        bus: BusConfig,
+        shared_bus: Arc<SharedBus>,
+        reconnect: ReconnectPolicy,
+    ) -> Arc<Self> {
+        Self::with_config(
+            app_handle,
+            shutdown,
+            bus,
+            shared_bus,
+            reconnect,
+            MediaPlayerConfig::default(),
+        )
+    }
+
+    pub fn with_config(
+        app_handle: AppHandle<Wry>,
+        shutdown: Arc<Shutdown>,
+        bus: BusConfig,
+        shared_bus: Arc<SharedBus>,
+        reconnect: ReconnectPolicy,
+        config: MediaPlayerConfig,
+    ) -> Arc<Self> {
+        let instance = Arc::new(Self {
+            app_handle,
+            emit_sender: Mutex::new(None),
+            connection: RwLock::new(None),
+            player_filter: RwLock::new(filter::PlayerFilter::new(&config)),
+            config: RwLock::new(config),
+            players: RwLock::new(HashMap::new()),
+            pinned_player: RwLock::new(None),
+            active_player: RwLock::new(None),
+            media_info: RwLock::new(MediaStruct::default()),
+            status_history: RwLock::new(HashMap::new()),
+            metrics: Mutex::new(metrics::MetricsRecorder::default()),
+            media_keys: Mutex::new(None),
+            player_states: RwLock::new(HashMap::new()),
+            art_colors: Mutex::new(HashMap::new()),
+            art_data_url: Mutex::new(None),
+            art_size: Mutex::new(None),
+            suppressed: Mutex::new(None),
+            bus,
+            shared_bus,
+            reconnect,
+            unavailable: RwLock::new(false),
+            restart: Notify::new(),
+            shutdown,
+        });
         instance.clone().start();
         instance
     }
 
-    fn start(self: Arc<Self>) {
-        // Create debounce function, listen all changes with MediaPlayer2
-        let sender = self.clone().create_emit_to_frontend();
+    pub async fn config(&self) -> MediaPlayerConfig {
+        self.config.read().await.clone()
+    }
 
-        // Create thread and listen for changes from ZBus:
-        thread::spawn(move || {
-            let runtime = tokio::runtime::Runtime::new().unwrap();
-            runtime.block_on(async move {
-                // Connect to ZBus and listen all changes
-                let connection_to_bus = Connection::session().await.unwrap();
-
-                let stream = MessageStream::from(connection_to_bus.clone());
-
-                // Rule listen only: PropertiesChanged
-                let dbus_proxy = DBusProxy::new(&connection_to_bus).await.unwrap();
-                dbus_proxy
-                    .add_match(
-                        "type='signal',interface='org.freedesktop.DBus.Properties',member='PropertiesChanged'",
-                    )
-                    .await
-                    .unwrap();
+    pub async fn set_config(&self, config: MediaPlayerConfig) {
+        if config.status_history_size == 0 {
+            self.status_history.write().await.clear();
+        }
 
-                // Listen events:
-                if let Err(err) = self.listen_events(stream, sender).await {
-                    eprintln!("TauriMediaPlayer err: {err}");
-                }
-            });
-        });
+        if !config.metrics {
+            *self.metrics.lock().unwrap() = metrics::MetricsRecorder::default();
+        }
+
+        *self.player_filter.write().await = filter::PlayerFilter::new(&config);
+        *self.config.write().await = config;
     }
 
-    fn create_emit_to_frontend(self: Arc<Self>) -> Sender<MediaStruct> {
-        // Create channel for debounce:
-        let (sender, receiver) = mpsc::channel::<MediaStruct>(1024);
-        // Create debounce listener:
-        let mut emit_event = Debounced::new(receiver, Duration::from_millis(100));
+    // Change truncation and resend current state truncated by the new length
+    pub async fn set_max_text_length(&self, max_length: usize) -> std::result::Result<(), String> {
+        // Config lock is released first, update paths take media_info before it:
+        let config = {
+            let mut config = self.config.write().await;
+            config.max_text_length = max_length;
+            config.clone()
+        };
 
-        // Create thread:
-        let send_self = self.clone();
-        thread::spawn(move || {
-            let runtime = tokio::runtime::Runtime::new().unwrap();
-            runtime.block_on(async move {
-                // Listen all changes from sender channel:
-                while let Some(media_info) = emit_event.next().await {
-                    if let Ok(json_string) = serde_json::to_string(&media_info) {
-                        // Send to frontend:
-                        let _ = send_self.app_handle.emit("onUpdateMediaMeta", json_string);
-                    }
-                }
+        let mut media_info_struct = self.media_info.write().await;
+        truncate_text(&mut media_info_struct, max_length);
+        update_now_playing_line(&mut media_info_struct, &config);
+        self.send_update(media_info_struct.clone())
+    }
+
+    pub async fn set_now_playing_template(
+        &self,
+        template: String,
+    ) -> std::result::Result<(), String> {
+        let config = {
+            let mut config = self.config.write().await;
+            config.now_playing_template = template;
+            config.clone()
+        };
+
+        let mut media_info_struct = self.media_info.write().await;
+        update_now_playing_line(&mut media_info_struct, &config);
+        self.send_update(media_info_struct.clone())
+    }
+
+    // Running players sorted by bus name, each tagged with it
+    pub async fn players(&self) -> Vec<MediaStruct> {
+        sorted_players(&*self.player_states.read().await)
+    }
+
+    // One-shot snapshot, Volume is read fresh from every player
+    pub async fn playing_players(&self) -> std::result::Result<Vec<PlayingPlayer>, String> {
+        let connection = self.connection().await?;
+        let player_states: Vec<(String, MediaStruct)> = self
+            .player_states
+            .read()
+            .await
+            .iter()
+            .filter(|(_, state)| state.status == "Playing")
+            .map(|(unique_name, state)| (unique_name.clone(), state.clone()))
+            .collect();
+
+        let mut playing_players = Vec::new();
+        for (unique_name, state) in player_states {
+            let identity = match self.players.read().await.get(&unique_name) {
+                Some(player_info) => player_info.identity.clone(),
+                None => String::new(),
+            };
+
+            playing_players.push(PlayingPlayer {
+                bus_name: state.bus_name,
+                identity,
+                title: state.title_full,
+                volume: fetch_volume(&connection, &unique_name).await,
             });
+        }
+        playing_players.sort_by(|a, b| a.bus_name.cmp(&b.bus_name));
+
+        Ok(playing_players)
+    }
+
+    pub fn metrics(&self) -> MediaMetrics {
+        let coalesced = match self.emit_sender.lock().unwrap().as_ref() {
+            Some(sender) => sender.coalesced(),
+            None => 0,
+        };
+        self.metrics.lock().unwrap().snapshot(coalesced)
+    }
+
+    pub async fn status_history(&self, bus_name: &str) -> Vec<StatusTransition> {
+        match self.status_history.read().await.get(bus_name) {
+            Some(history) => history.iter().cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub async fn pin_player(&self, bus_name: String) -> std::result::Result<(), String> {
+        if !bus_name.starts_with("org.mpris.MediaPlayer2.") {
+            return Err(format!("Not a MPRIS player name: {bus_name}"));
+        }
+
+        // Not connected yet, owner is resolved on the next message:
+        let connection = self.connection().await.ok();
+        let owner = match &connection {
+            Some(connection) => match DBusProxy::new(connection).await {
+                Ok(dbus_proxy) => name_owner(&dbus_proxy, &bus_name).await,
+                Err(_) => None,
+            },
+            None => None,
+        };
+        *self.pinned_player.write().await = Some(PinnedPlayer {
+            bus_name: bus_name.clone(),
+            owner,
+            resolved: connection.is_some(),
         });
+        let _ = Emit::emit(
+            &self.app_handle,
+            "media_player",
+            PINNED_EVENT,
+            Some(bus_name),
+        );
 
-        sender
+        // Show the pinned player right away, or a clear state without it:
+        self.reselect().await;
+        Ok(())
     }
 
-    async fn listen_events(
+    pub async fn unpin(&self) {
+        *self.pinned_player.write().await = None;
+        let _ = Emit::emit(
+            &self.app_handle,
+            "media_player",
+            PINNED_EVENT,
+            None::<String>,
+        );
+
+        self.reselect().await;
+    }
+
+    // Pick the shown player again from all running ones, skipped while
+    // not connected since the initial state is sent on connect anyway
+    async fn reselect(&self) {
+        let Ok(connection) = self.connection().await else {
+            return;
+        };
+        let sender = self.emit_sender.lock().unwrap().clone();
+        if let Some(sender) = sender {
+            self.emit_initial_state(&connection, &sender).await;
+        }
+    }
+
+    pub async fn pinned_player(&self) -> Option<String> {
+        self.pinned_player
+            .read()
+            .await
+            .as_ref()
+            .map(|v| v.bus_name.clone())
+    }
+
+    async fn connection(&self) -> std::result::Result<Connection, String> {
+        match self.connection.read().await.as_ref() {
+            Some(connection) => Ok(connection.clone()),
+            None => Err("Media player is not connected".to_string()),
+        }
+    }
+
+    async fn active_player_name(&self) -> std::result::Result<String, String> {
+        self.active_player
+            .read()
+            .await
+            .clone()
+            .ok_or_else(|| "No active media player".to_string())
+    }
+
+    // Read property of org.mpris.MediaPlayer2.Player on the active player
+    async fn player_property(&self, property: &str) -> std::result::Result<OwnedValue, String> {
+        let connection = self.connection().await?;
+        let active_player = self.active_player_name().await?;
+
+        let proxy = PropertiesProxy::builder(&connection)
+            .destination(active_player)
+            .map_err(|e| e.to_string())?
+            .path("/org/mpris/MediaPlayer2")
+            .map_err(|e| e.to_string())?
+            .build()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        proxy
+            .get(
+                InterfaceName::from_static_str_unchecked("org.mpris.MediaPlayer2.Player"),
+                property,
+            )
+            .await
+            .map_err(|e| format!("{property} is not available: {e}"))
+    }
+
+    // Call method of org.mpris.MediaPlayer2.Player on the active player
+    pub async fn control(&self, method: &str) -> std::result::Result<(), String> {
+        self.call_player(method, &()).await
+    }
+
+    // Bring the active player window to front, Raise is on the root interface
+    pub async fn raise(&self) -> std::result::Result<(), String> {
+        if !self.media_info.read().await.capabilities.can_raise {
+            return Err("Active media player can't raise".to_string());
+        }
+
+        let connection = self.connection().await?;
+        let active_player = self.active_player_name().await?;
+        connection
+            .call_method(
+                Some(active_player.as_str()),
+                "/org/mpris/MediaPlayer2",
+                Some("org.mpris.MediaPlayer2"),
+                "Raise",
+                &(),
+            )
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    // SetPosition needs the current track id, it is ignored for other tracks
+    pub async fn seek(&self, position_us: i64) -> std::result::Result<(), String> {
+        let media_info = self.media_info.read().await.clone();
+        if !media_info.capabilities.can_seek {
+            return Err("Active media player can't seek".to_string());
+        }
+        if media_info.track_id.is_empty() {
+            return Err("Track has no mpris:trackid to seek in".to_string());
+        }
+        if position_us < 0 || (media_info.length_us > 0 && position_us > media_info.length_us) {
+            return Err(format!("Position {position_us} is outside of the track"));
+        }
+
+        let track_id = ObjectPath::try_from(media_info.track_id).map_err(|e| e.to_string())?;
+        self.call_player("SetPosition", &(track_id, position_us))
+            .await
+    }
+
+    // Clamped to 0.0..=1.0, sent to frontend without waiting for the signal
+    pub async fn set_volume(&self, value: f64) -> std::result::Result<(), String> {
+        if value.is_nan() {
+            return Err("Volume is not a number".to_string());
+        }
+        let volume = value.clamp(0.0, 1.0);
+        self.set_player_property("Volume", Value::F64(volume))
+            .await?;
+
+        let mut media_info_struct = self.media_info.write().await;
+        media_info_struct.volume = volume;
+        self.send_update(media_info_struct.clone())
+    }
+
+    // Clamped to MinimumRate and MaximumRate, players without them only get
+    // the value checked. 0.0 is not a rate, Pause is used for that
+    pub async fn set_rate(&self, value: f64) -> std::result::Result<(), String> {
+        if value.is_nan() || value <= 0.0 {
+            return Err("Rate must be a positive number".to_string());
+        }
+
+        let bound = |property: OwnedValue| property.downcast_ref::<f64>().copied();
+        let minimum = self
+            .player_property("MinimumRate")
+            .await
+            .ok()
+            .and_then(bound);
+        let maximum = self
+            .player_property("MaximumRate")
+            .await
+            .ok()
+            .and_then(bound);
+        let mut rate = value;
+        if let Some(minimum) = minimum.filter(|v| *v > 0.0) {
+            rate = rate.max(minimum);
+        }
+        if let Some(maximum) = maximum {
+            rate = rate.min(maximum);
+        }
+        self.set_player_property("Rate", Value::F64(rate)).await?;
+
+        let mut media_info_struct = self.media_info.write().await;
+        media_info_struct.rate = PlaybackRate(rate);
+        self.send_update(media_info_struct.clone())
+    }
+
+    pub async fn toggle_shuffle(&self) -> std::result::Result<(), String> {
+        let shuffle = self
+            .player_property("Shuffle")
+            .await
+            .ok()
+            .and_then(|v| v.downcast_ref::<bool>().copied())
+            .ok_or_else(|| "Active media player has no Shuffle".to_string())?;
+        self.set_player_property("Shuffle", Value::Bool(!shuffle))
+            .await?;
+
+        let mut media_info_struct = self.media_info.write().await;
+        media_info_struct.shuffle = !shuffle;
+        self.send_update(media_info_struct.clone())
+    }
+
+    // None -> Playlist -> Track -> None
+    pub async fn cycle_loop(&self) -> std::result::Result<(), String> {
+        let loop_status = self
+            .player_property("LoopStatus")
+            .await
+            .ok()
+            .and_then(|v| v.downcast_ref::<str>().map(|v| v.to_string()))
+            .ok_or_else(|| "Active media player has no LoopStatus".to_string())?;
+        let loop_status = match loop_status.as_str() {
+            "None" => "Playlist",
+            "Playlist" => "Track",
+            _ => "None",
+        };
+        self.set_player_property("LoopStatus", Value::from(loop_status))
+            .await?;
+
+        let mut media_info_struct = self.media_info.write().await;
+        media_info_struct.loop_status = loop_status.to_string();
+        self.send_update(media_info_struct.clone())
+    }
+
+    // Write property of org.mpris.MediaPlayer2.Player on the active player
+    async fn set_player_property(
         &self,
-        mut stream: MessageStream,
-        mut debounce_sender: Sender<MediaStruct>,
-    ) -> zbus::Result<()> {
-        // Media struct: (Using to send debounce)
-        let mut media_info_struct = MediaStruct {
-            title: "".to_string(),
-            artist: Vec::new(),
-            album: "".to_string(),
-            status: "".to_string(),
+        property: &str,
+        value: Value<'_>,
+    ) -> std::result::Result<(), String> {
+        let connection = self.connection().await?;
+        let active_player = self.active_player_name().await?;
+        let proxy = PropertiesProxy::builder(&connection)
+            .destination(active_player)
+            .map_err(|e| e.to_string())?
+            .path("/org/mpris/MediaPlayer2")
+            .map_err(|e| e.to_string())?
+            .build()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        proxy
+            .set(
+                InterfaceName::from_static_str_unchecked("org.mpris.MediaPlayer2.Player"),
+                property,
+                &value,
+            )
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn call_player<B>(&self, method: &str, body: &B) -> std::result::Result<(), String>
+    where
+        B: Serialize + DynamicType,
+    {
+        let connection = self.connection().await?;
+        let active_player = self.active_player_name().await?;
+
+        connection
+            .call_method(
+                Some(active_player.as_str()),
+                "/org/mpris/MediaPlayer2",
+                Some("org.mpris.MediaPlayer2.Player"),
+                method,
+                body,
+            )
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    // Route media keys to the active player, until disabled
+    pub async fn enable_media_keys(self: Arc<Self>) -> std::result::Result<(), String> {
+        // Checked and stored under one guard, so racing calls grab once. A
+        // finished task failed to grab or lost the keys, it can be replaced
+        let mut media_keys = self.media_keys.lock().unwrap();
+        if media_keys.as_ref().is_some_and(|task| !task.is_finished()) {
+            return Err("Media keys are already grabbed".to_string());
+        }
+
+        let media_player = self.clone();
+        let task = tokio::spawn(async move {
+            if let Err(err) = media_keys::grab(media_player).await {
+                Log::error(
+                    "media_player",
+                    format!("TauriMediaPlayer media keys err: {err}"),
+                );
+            }
+        });
+        *media_keys = Some(task);
+
+        Ok(())
+    }
+
+    pub async fn disable_media_keys(&self) -> std::result::Result<(), String> {
+        let task = self.media_keys.lock().unwrap().take();
+        match task {
+            Some(task) => {
+                task.abort();
+                media_keys::release(self).await
+            }
+            None => Ok(()),
+        }
+    }
+
+    // Read Position right now, and update frontend with it
+    pub async fn position(&self) -> std::result::Result<i64, String> {
+        let position = self.player_property("Position").await?;
+        let position =
+            integer(Some(&position)).ok_or_else(|| "Position is not available".to_string())?;
+
+        let mut media_info_struct = self.media_info.write().await;
+        media_info_struct.position_us = position;
+        self.send_update(media_info_struct.clone())?;
+
+        Ok(position)
+    }
+
+    pub async fn progress(&self) -> std::result::Result<MediaProgress, String> {
+        let connection = self.connection().await?;
+        let active_player = self.active_player_name().await?;
+
+        let props = fetch_player_props(&connection, &active_player)
+            .await
+            .map_err(|e| e.to_string())?;
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|v| v.as_millis())
+            .unwrap_or_default();
+
+        let metadata = match props.get("Metadata") {
+            Some(Value::Dict(metadata)) if has_string_keys(metadata) => Some(metadata),
+            _ => None,
         };
+        let position_us = integer(props.get("Position"))
+            .ok_or_else(|| "Position is not available".to_string())?;
+        let length_raw = metadata
+            .and_then(|v| integer(v.get("mpris:length").unwrap_or_default()))
+            .unwrap_or_default();
+        let bus_name = self.media_info.read().await.bus_name.clone();
+        let config = self.config.read().await;
 
-        // Await new message
-        while let Some(event_message) = stream.next().await {
-            if let Ok(event_message) = event_message {
-                // If header member is PropertiesChanged
-                let header = event_message.header()?;
-                let member_as_str = match header.member().unwrap() {
-                    Some(value) => value.as_str(),
-                    None => "",
-                };
+        Ok(MediaProgress {
+            position_us,
+            length_us: length::length_us(
+                length_raw,
+                position_us,
+                config.length_units.get(&bus_name).copied(),
+                config.guess_length_unit,
+            ),
+            // Rate is optional, 1.0 is the normal speed:
+            rate: match props.get("Rate") {
+                Some(Value::F64(rate)) => *rate,
+                _ => 1.0,
+            },
+            status: parse_status(&props).unwrap_or_default(),
+            track_id: metadata.and_then(metadata_track_id).unwrap_or_default(),
+            timestamp_ms,
+        })
+    }
 
-                // If not PropertiesChanged skip
-                if member_as_str != "PropertiesChanged" {
-                    continue;
-                }
+    // Every property of root and Player interfaces by interface name, as
+    // the player reports them. For inspecting players that look wrong
+    pub async fn full_snapshot(
+        &self,
+        bus_name: &str,
+    ) -> std::result::Result<JsonValue, MediaSnapshotError> {
+        let connection = self
+            .connection()
+            .await
+            .map_err(MediaSnapshotError::NotStarted)?;
 
-                // Try parse body:
-                if let Ok((body_interface, body_props, _)) = event_message.body::<(
-                    String,
-                    std::collections::HashMap<String, Value>,
-                    Vec<String>,
-                )>() {
-                    // If is not MediaPlayer skip:
-                    if !body_interface.starts_with("org.mpris.MediaPlayer2.Player") {
-                        continue;
+        let mut snapshot = JsonMap::new();
+        for interface in ["org.mpris.MediaPlayer2", "org.mpris.MediaPlayer2.Player"] {
+            let props = fetch_props(&connection, bus_name, interface)
+                .await
+                .map_err(|e| match e {
+                    zbus::Error::FDO(e)
+                        if matches!(
+                            *e,
+                            zbus::fdo::Error::ServiceUnknown(_)
+                                | zbus::fdo::Error::NameHasNoOwner(_)
+                                | zbus::fdo::Error::UnknownObject(_)
+                        ) =>
+                    {
+                        MediaSnapshotError::PlayerGone(format!("{bus_name}: {e}"))
                     }
+                    e => MediaSnapshotError::Failed(format!("{bus_name}: {e}")),
+                })?;
 
-                    // Getting playing status:
-                    let playing_status = match body_props.get("PlaybackStatus") {
-                        Some(v) => v.to_string(),
-                        None => String::new(),
-                    };
+            let props = props
+                .iter()
+                .map(|(key, value)| (key.clone(), value_to_json(value)))
+                .collect();
+            snapshot.insert(interface.to_string(), JsonValue::Object(props));
+        }
 
-                    let metadata = match body_props.get("Metadata") {
-                        Some(Value::Dict(dict)) => Some(dict),
-                        _ => None,
-                    };
+        Ok(JsonValue::Object(snapshot))
+    }
 
-                    // Getting is Play state:
-                    if !playing_status.is_empty() {
-                        // Update, and send to debounce:
-                        media_info_struct.status = playing_status.to_string().replace("\"", "");
+    pub fn inject(&self, media_info: MediaStruct) -> std::result::Result<(), String> {
+        self.send_update(media_info)
+    }
 
-                        if let Err(_) = debounce_sender.try_send(media_info_struct.clone()) {}
-                    }
-                    // Getting metadata:
-                    else if let Some(metadata) = metadata {
-                        let title = match metadata.get("xesam:title").unwrap() {
-                            Some(Value::Str(v)) => v.to_string(),
-                            _ => String::new(),
-                        };
+    pub async fn available(&self) -> bool {
+        !*self.unavailable.read().await
+    }
 
-                        let album = match metadata.get("xesam:album").unwrap() {
-                            Some(Value::Str(v)) => v.to_string(),
-                            _ => String::new(),
-                        };
+    // Retry the bus after the reconnect policy gave up
+    pub async fn restart(&self) -> std::result::Result<(), String> {
+        if !*self.unavailable.read().await {
+            return Err("Media player is connected or still reconnecting".to_string());
+        }
+        self.restart.notify_one();
+        Ok(())
+    }
 
-                        let artist = match metadata.get("xesam:artist").unwrap() {
-                            Some(Value::Array(arr)) => arr
-                                .iter()
-                                .filter_map(|v| v.downcast_ref::<str>().map(|a| a.to_string()))
-                                .collect(),
-                            _ => Vec::new(),
-                        };
+    // Send the update skipped while hidden, once the window is shown again
+    fn flush_suppressed(&self) {
+        let media_info = self.suppressed.lock().unwrap().take();
+        if let Some(media_info) = media_info {
+            if let Err(err) = self.send_update(media_info) {
+                Log::error("media_player", format!("TauriMediaPlayer flush err: {err}"));
+            }
+        }
+    }
+
+    fn window_visible(&self) -> bool {
+        self.app_handle
+            .get_webview_window("main")
+            .and_then(|window| window.is_visible().ok())
+            .unwrap_or(true)
+    }
+
+    // Push media struct through the usual debounce and emit path
+    fn send_update(&self, media_info: MediaStruct) -> std::result::Result<(), String> {
+        let sender = self.emit_sender.lock().unwrap().clone();
+        match sender {
+            Some(sender) => sender.send(media_info),
+            None => Err("Media player is not started".to_string()),
+        }
+    }
+
+    fn start(self: Arc<Self>) {
+        // Create debounce function, listen all changes with MediaPlayer2
+        let sender = self.clone().create_emit_to_frontend();
+        *self.emit_sender.lock().unwrap() = Some(sender.clone());
 
-                        // Update, and send to debounce:
-                        media_info_struct.artist = artist;
-                        media_info_struct.album = album;
-                        media_info_struct.title = title;
+        // Create task and listen for changes from ZBus:
+        let shutdown = self.shutdown.clone();
+        shutdown.spawn(async move {
+            tokio::spawn(self.clone().poll_position());
+
+            // Bus can be unreachable or restart (session change), other
+            // modules keep working and this one reconnects with backoff:
+            let shutdown = self.shutdown.token();
+            let mut backoff = Backoff::new(self.reconnect.clone());
+            let mut lost = false;
+            loop {
+                match self.connect_session(&sender).await {
+                    Ok((connection, dbus_proxy, stream, statuses)) => {
+                        if lost {
+                            lost = false;
+                            let _ = Emit::emit(
+                                &self.app_handle,
+                                "media_player",
+                                "onMediaConnectionRestored",
+                                (),
+                            );
+                        }
+                        backoff.reset();
 
-                        if let Err(err) = debounce_sender.try_send(media_info_struct.clone()) {
-                            eprintln!("TauriMediaPlayer debounce err: {err}");
+                        // Listen events, until the stream ends:
+                        if let Err(err) = self
+                            .listen_events(
+                                &connection,
+                                &dbus_proxy,
+                                stream,
+                                sender.clone(),
+                                statuses,
+                            )
+                            .await
+                        {
+                            Log::error("media_player", format!("TauriMediaPlayer err: {err}"));
                         }
                     }
+                    Err(err) => Log::error("media_player", format!("TauriMediaPlayer err: {err}")),
+                }
+
+                if shutdown.is_cancelled() {
+                    break;
+                }
+
+                // Unique names don't survive the connection:
+                if let Some(connection) = self.connection.write().await.take() {
+                    self.shared_bus.invalidate(&self.bus, &connection).await;
+                }
+                *self.active_player.write().await = None;
+                self.players.write().await.clear();
+                self.player_states.write().await.clear();
+                if !lost {
+                    lost = true;
+                    let _ = Emit::emit(
+                        &self.app_handle,
+                        "media_player",
+                        "onMediaConnectionLost",
+                        (),
+                    );
+                }
+
+                let Some(delay) = backoff.next_delay() else {
+                    // Bus looks permanently dead, stop spinning on it:
+                    Log::warn(
+                        "media_player",
+                        "TauriMediaPlayer: reconnect gave up, waiting for restart",
+                    );
+                    *self.unavailable.write().await = true;
+                    let _ = Emit::emit(&self.app_handle, "media_player", "onMediaUnavailable", ());
+
+                    tokio::select! {
+                        _ = shutdown.cancelled() => break,
+                        _ = self.restart.notified() => {}
+                    }
+                    *self.unavailable.write().await = false;
+                    backoff.reset();
+                    continue;
+                };
+
+                tokio::select! {
+                    _ = shutdown.cancelled() => break,
+                    _ = tokio::time::sleep(delay) => {}
                 }
             }
+        });
+    }
+
+    // Connect, subscribe and send state of running players
+    async fn connect_session(
+        &self,
+        sender: &LatestSender<MediaStruct>,
+    ) -> zbus::Result<(
+        Connection,
+        DBusProxy<'static>,
+        MessageStream,
+        HashMap<String, String>,
+    )> {
+        let connection_to_bus = self.shared_bus.connect(&self.bus).await?;
+        *self.connection.write().await = Some(connection_to_bus.clone());
+
+        // Connection is shared, the stream also gets messages of other
+        // modules' rules. listen_events checks what it handles
+        let stream = MessageStream::from(connection_to_bus.clone());
+
+        // Rule listen only: PropertiesChanged
+        let dbus_proxy = DBusProxy::new(&connection_to_bus).await?;
+        let properties_rule = MatchRule::builder()
+            .msg_type(MessageType::Signal)
+            .interface("org.freedesktop.DBus.Properties")?
+            .member("PropertiesChanged")?
+            .build();
+        dbus_proxy.add_match_rule(properties_rule).await?;
+
+        // And owner changes of players (for pinned player):
+        let owner_rule = MatchRule::builder()
+            .msg_type(MessageType::Signal)
+            .interface("org.freedesktop.DBus")?
+            .member("NameOwnerChanged")?
+            .arg0ns("org.mpris.MediaPlayer2")?
+            .build();
+        dbus_proxy.add_match_rule(owner_rule).await?;
+
+        // Remember already running players:
+        self.load_players(&connection_to_bus, &dbus_proxy).await;
+        let statuses = self.emit_initial_state(&connection_to_bus, sender).await;
+
+        Ok((connection_to_bus, dbus_proxy, stream, statuses))
+    }
+
+    // Emits directly, a steady 1s poll would keep the position debounce
+    // from ever firing
+    async fn poll_position(self: Arc<Self>) {
+        let shutdown = self.shutdown.token();
+        let mut interval = tokio::time::interval(Duration::from_millis(POSITION_POLL_MS));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = interval.tick() => {}
+            }
+
+            if self.media_info.read().await.status != "Playing" {
+                continue;
+            }
+
+            // Player may be gone or not report Position at all:
+            let position = match self.player_property("Position").await {
+                Ok(position) => match integer(Some(&position)) {
+                    Some(position) => position,
+                    None => continue,
+                },
+                Err(_) => continue,
+            };
+
+            let config = self.config.read().await.clone();
+            let media_info = {
+                let mut media_info_struct = self.media_info.write().await;
+                if media_info_struct.position_us == position {
+                    continue;
+                }
+                media_info_struct.position_us = position;
+                update_length(&mut media_info_struct, &config);
+                media_info_struct.clone()
+            };
+            self.emit_to_frontend(media_info).await;
         }
+    }
 
-        Ok(())
+    fn create_emit_to_frontend(self: Arc<Self>) -> LatestSender<MediaStruct> {
+        // Create channel for debounce, only the newest state is worth routing:
+        let (sender, mut receiver) = latest::channel::<MediaStruct>();
+
+        // Create task:
+        let send_self = self.clone();
+        let shutdown = self.shutdown.token();
+        self.shutdown.spawn(async move {
+            // Own debounce per field group, intervals are read once:
+            let config = send_self.config.read().await.clone();
+            let (mut status_sender, status_receiver) = mpsc::channel(1024);
+            let (mut metadata_sender, metadata_receiver) = mpsc::channel(1024);
+            let (mut position_sender, position_receiver) = mpsc::channel(1024);
+            let mut status_event = Debounced::new(
+                status_receiver,
+                Duration::from_millis(config.status_debounce_ms),
+            );
+            let mut metadata_event = Debounced::new(
+                metadata_receiver,
+                Duration::from_millis(config.metadata_debounce_ms),
+            );
+            let mut position_event = Debounced::new(
+                position_receiver,
+                Duration::from_millis(config.position_debounce_ms),
+            );
+
+            // Updates are numbered, so a group firing late can't emit older state:
+            let mut last_routed = MediaStruct::default();
+            let mut routed_count: u64 = 0;
+            let mut emitted_count: u64 = 0;
+            // Chatty players resend the same state, it is emitted once:
+            let mut last_emitted: Option<MediaStruct> = None;
+            // Panels are shown without taking focus, so visibility is polled,
+            // only while a suppressed update waits:
+            let mut visibility = tokio::time::interval(Duration::from_millis(VISIBILITY_POLL_MS));
+            visibility.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+            loop {
+                // Listen all changes from sender channel, until exit:
+                let (count, media_info): (u64, MediaStruct) = tokio::select! {
+                    media_info = receiver.recv() => {
+                        routed_count += 1;
+                        let group_sender = if media_info.status != last_routed.status {
+                            &mut status_sender
+                        } else if only_position_changed(&last_routed, &media_info) {
+                            &mut position_sender
+                        } else {
+                            &mut metadata_sender
+                        };
+
+                        last_routed = media_info.clone();
+                        let _ = group_sender.try_send((routed_count, media_info));
+                        continue;
+                    }
+                    Some(update) = status_event.next() => update,
+                    Some(update) = metadata_event.next() => update,
+                    Some(update) = position_event.next() => update,
+                    _ = visibility.tick(), if send_self.suppressed.lock().unwrap().is_some() => {
+                        if send_self.window_visible() {
+                            send_self.flush_suppressed();
+                        }
+                        continue;
+                    }
+                    _ = shutdown.cancelled() => break,
+                    else => break,
+                };
+
+                if count <= emitted_count {
+                    continue;
+                }
+                emitted_count = count;
+                if last_emitted.as_ref() == Some(&media_info) {
+                    continue;
+                }
+                if send_self.emit_to_frontend(media_info.clone()).await {
+                    last_emitted = Some(media_info);
+                }
+            }
+        });
+
+        sender
+    }
+
+    // False when the update was suppressed or not sent
+    async fn emit_to_frontend(&self, media_info: MediaStruct) -> bool {
+        // Keep only the latest state while nobody can see it:
+        if self.config.read().await.emit_only_when_visible && !self.window_visible() {
+            *self.suppressed.lock().unwrap() = Some(media_info);
+            return false;
+        }
+
+        let config = self.config.read().await.clone();
+        let emitted = if config.structured_payload {
+            Emit::emit(&self.app_handle, "media_player", EMIT_EVENT, media_info)
+        } else {
+            match serde_json::to_string(&media_info) {
+                Ok(json_string) => {
+                    Emit::emit(&self.app_handle, "media_player", EMIT_EVENT, json_string)
+                }
+                Err(err) => {
+                    Log::error("media_player", format!("TauriMediaPlayer emit err: {err}"));
+                    return false;
+                }
+            }
+        };
+
+        if emitted.is_ok() && config.metrics {
+            self.metrics.lock().unwrap().record_emitted();
+        }
+        emitted.is_ok()
+    }
+
+    async fn listen_events(
+        &self,
+        connection: &Connection,
+        dbus_proxy: &DBusProxy<'_>,
+        mut stream: MessageStream,
+        debounce_sender: LatestSender<MediaStruct>,
+        // Last PlaybackStatus of every player by unique name:
+        mut statuses: HashMap<String, String>,
+    ) -> zbus::Result<()> {
+        // Playing players by unique name, pinned and filtered ones included:
+        let mut playing: HashSet<String> = statuses
+            .iter()
+            .filter(|(_, status)| status.as_str() == "Playing")
+            .map(|(name, _)| name.clone())
+            .collect();
+        // Last mpris:trackid of every player by unique name:
+        let mut track_ids: HashMap<String, String> = HashMap::new();
+        // Player waiting to become active, and when:
+        let mut pending_switch: Option<(String, Instant)> = None;
+        // When the shown player last went Playing, for the transient hint:
+        let mut playing_since: Option<Instant> = None;
+        let shutdown = self.shutdown.token();
+
+        loop {
+            // Await new message, the pending player switch or exit:
+            let deadline = pending_switch.as_ref().map(|(_, deadline)| *deadline);
+            let event_message = match next_event(&mut stream, &shutdown, deadline).await {
+                Wake::Shutdown => break,
+                Wake::Message(event_message) => event_message,
+                Wake::Switch => {
+                    let Some((candidate, _)) = pending_switch.take() else {
+                        continue;
+                    };
+
+                    // Switch only if it is still the sole playing one:
+                    if sole_playing(&statuses) == Some(candidate.as_str()) {
+                        match fetch_player_props(connection, &candidate).await {
+                            Ok(props) => {
+                                let mut media_info_struct = self.media_info.write().await;
+                                *media_info_struct = MediaStruct {
+                                    any_playing: !playing.is_empty(),
+                                    ..MediaStruct::default()
+                                };
+                                self.set_player_info(&mut media_info_struct, &candidate)
+                                    .await;
+                                let config = self.config.read().await.clone();
+                                apply_capabilities(&mut media_info_struct.capabilities, &props);
+                                apply_properties(&mut media_info_struct, &props, &config);
+                                self.update_art(&mut media_info_struct).await;
+                                *self.active_player.write().await = Some(candidate);
+
+                                if let Err(err) = debounce_sender.send(media_info_struct.clone()) {
+                                    Log::error(
+                                        "media_player",
+                                        format!("TauriMediaPlayer debounce err: {err}"),
+                                    );
+                                }
+                            }
+                            Err(err) => Log::error(
+                                "media_player",
+                                format!("TauriMediaPlayer switch err: {err}"),
+                            ),
+                        }
+                    }
+                    continue;
+                }
+            };
+
+            let event_message = match event_message {
+                Some(event_message) => event_message,
+                None => break,
+            };
+
+            if let Ok(event_message) = event_message {
+                // If header member is PropertiesChanged
+                let header = event_message.header()?;
+                let member_as_str = match header.member() {
+                    Ok(Some(value)) => value.as_str(),
+                    _ => "",
+                };
+
+                // Player appeared or gone:
+                if member_as_str == "NameOwnerChanged" {
+                    if let Ok((name, old_owner, new_owner)) =
+                        event_message.body::<(String, String, String)>()
+                    {
+                        // Same as our rule, the tray watches all names:
+                        if !name.starts_with("org.mpris.MediaPlayer2.") {
+                            continue;
+                        }
+
+                        self.update_players(connection, &name, &old_owner, &new_owner)
+                            .await;
+
+                        let mut active_gone = false;
+                        if !old_owner.is_empty() {
+                            self.remove_player_state(&old_owner).await;
+                            statuses.remove(&old_owner);
+                            track_ids.remove(&old_owner);
+                            if playing.remove(&old_owner) {
+                                self.update_any_playing(!playing.is_empty(), &debounce_sender)
+                                    .await;
+                            }
+
+                            let mut active_player = self.active_player.write().await;
+                            if active_player.as_deref() == Some(old_owner.as_str()) {
+                                *active_player = None;
+                                active_gone = true;
+                            }
+                        }
+
+                        // Shown player quit, don't leave its track frozen in the widget:
+                        let pinned_gone = self.update_pinned_owner(&name, &new_owner).await;
+                        if active_gone || pinned_gone {
+                            let mut media_info_struct = self.media_info.write().await;
+                            *media_info_struct = MediaStruct::cleared();
+                            media_info_struct.any_playing = !playing.is_empty();
+
+                            if let Err(err) = debounce_sender.send(media_info_struct.clone()) {
+                                Log::error(
+                                    "media_player",
+                                    format!("TauriMediaPlayer debounce err: {err}"),
+                                );
+                            }
+                        }
+                    }
+                    continue;
+                }
+
+                // If not PropertiesChanged skip
+                if member_as_str != "PropertiesChanged" {
+                    continue;
+                }
+
+                let sender = header.sender().ok().flatten().map(|v| v.to_string());
+
+                // Track playing of all players, before any of them are skipped:
+                if let (Some(sender), Ok((body_interface, body_props, _))) = (
+                    &sender,
+                    event_message.body::<(String, HashMap<String, Value>, Vec<String>)>(),
+                ) {
+                    if body_interface.starts_with("org.mpris.MediaPlayer2.Player") {
+                        if self.is_allowed_sender(Some(sender)).await {
+                            self.update_player_state(sender, &body_props).await;
+                        }
+
+                        if let Some(playing_status) = parse_status(&body_props) {
+                            if playing_status == "Playing" {
+                                playing.insert(sender.clone());
+                            } else {
+                                playing.remove(sender);
+                            }
+                            self.update_any_playing(!playing.is_empty(), &debounce_sender)
+                                .await;
+                        }
+                    }
+                }
+
+                // If pinned to other player skip
+                if !self.is_pinned_sender(dbus_proxy, sender.as_deref()).await {
+                    continue;
+                }
+
+                // Players excluded by allow/deny patterns are never selected:
+                if !self.is_allowed_sender(sender.as_deref()).await {
+                    continue;
+                }
+
+                // Try parse body:
+                if let Ok((body_interface, body_props, _)) =
+                    event_message.body::<(String, HashMap<String, Value>, Vec<String>)>()
+                {
+                    // If is not MediaPlayer skip:
+                    if !body_interface.starts_with("org.mpris.MediaPlayer2.Player") {
+                        continue;
+                    }
+
+                    if self.config.read().await.metrics {
+                        let has_status = body_props.contains_key("PlaybackStatus");
+                        self.metrics.lock().unwrap().record_received(has_status);
+                    }
+
+                    // Getting playing status:
+                    if let Some(playing_status) = parse_status(&body_props) {
+                        self.record_status(sender.as_deref(), &playing_status).await;
+
+                        if let Some(sender) = &sender {
+                            statuses.insert(sender.clone(), playing_status);
+                        }
+                    }
+
+                    // Focus follows playing: other players take over only after a delay
+                    let is_active = self.accept_active_player(sender.as_deref()).await;
+                    let switch_delay = self.config.read().await.switch_delay_ms;
+
+                    if switch_delay > 0 && self.pinned_player.read().await.is_none() {
+                        let active_player = self.active_player.read().await.clone();
+
+                        pending_switch = match sole_playing(&statuses) {
+                            Some(candidate) if Some(candidate) != active_player.as_deref() => {
+                                match pending_switch {
+                                    // Keep deadline, the same player is still waiting:
+                                    Some((pending, deadline)) if pending == candidate => {
+                                        Some((pending, deadline))
+                                    }
+                                    _ => Some((
+                                        candidate.to_string(),
+                                        Instant::now() + Duration::from_millis(switch_delay),
+                                    )),
+                                }
+                            }
+                            _ => None,
+                        };
+                    }
+
+                    if !is_active {
+                        continue;
+                    }
+
+                    let mut media_info_struct = self.media_info.write().await;
+                    if let Some(sender) = &sender {
+                        self.set_player_info(&mut media_info_struct, sender).await;
+                    }
+
+                    // New track without a status change in between:
+                    if let (Some(sender), Some(track_id)) = (&sender, track_id(&body_props)) {
+                        let previous = track_ids.insert(sender.clone(), track_id.clone());
+                        media_info_struct.track_changed = is_track_change(
+                            previous.as_deref(),
+                            &track_id,
+                            &media_info_struct.status,
+                            parse_status(&body_props).as_deref(),
+                        );
+                    }
+
+                    // Capabilities first, so a combined update sends the new flags:
+                    let capabilities_changed =
+                        apply_capabilities(&mut media_info_struct.capabilities, &body_props);
+
+                    // Update, and send to debounce:
+                    let config = self.config.read().await.clone();
+                    let previous_status = media_info_struct.status.clone();
+                    let applied = apply_properties(&mut media_info_struct, &body_props, &config);
+                    if applied {
+                        let playing = media_info_struct.status == "Playing";
+                        let stopped_quickly = previous_status == "Playing"
+                            && !playing
+                            && playing_since.is_some_and(|v| {
+                                v.elapsed() <= Duration::from_millis(config.transient_stop_ms)
+                            });
+                        if playing && previous_status != "Playing" {
+                            playing_since = Some(Instant::now());
+                        }
+                        media_info_struct.transient =
+                            is_transient(&media_info_struct, stopped_quickly, &config);
+
+                        self.update_art(&mut media_info_struct).await;
+                        if let Err(err) = debounce_sender.send(media_info_struct.clone()) {
+                            Log::error(
+                                "media_player",
+                                format!("TauriMediaPlayer debounce err: {err}"),
+                            );
+                        }
+                    }
+
+                    // Capability only flips go out on their own, without metadata:
+                    if capabilities_changed && !applied {
+                        let _ = Emit::emit(
+                            &self.app_handle,
+                            "media_player",
+                            "onMediaCapabilities",
+                            MediaCapabilitiesEvent {
+                                bus_name: media_info_struct.bus_name.clone(),
+                                capabilities: media_info_struct.capabilities.clone(),
+                            },
+                        );
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Without switch delay (or while pinned) every sender becomes active
+    async fn accept_active_player(&self, sender: Option<&str>) -> bool {
+        let switch_delay = self.config.read().await.switch_delay_ms;
+        let is_pinned = self.pinned_player.read().await.is_some();
+
+        let mut active_player = self.active_player.write().await;
+        if switch_delay == 0 || is_pinned || active_player.is_none() {
+            *active_player = sender.map(|v| v.to_string());
+            return true;
+        }
+
+        active_player.as_deref() == sender
+    }
+
+    async fn load_players(&self, connection: &Connection, dbus_proxy: &DBusProxy<'_>) {
+        let names = match dbus_proxy.list_names().await {
+            Ok(names) => names,
+            Err(err) => {
+                Log::error(
+                    "media_player",
+                    format!("TauriMediaPlayer list names err: {err}"),
+                );
+                return;
+            }
+        };
+
+        for name in names {
+            if !name.starts_with("org.mpris.MediaPlayer2.") {
+                continue;
+            }
+
+            if let Ok(owner) = dbus_proxy.get_name_owner(name.inner().clone()).await {
+                let player_info = fetch_player_info(connection, owner.as_str(), &name).await;
+                self.players
+                    .write()
+                    .await
+                    .insert(owner.to_string(), player_info);
+            }
+        }
+    }
+
+    // Send state of already running player, Playing one first, so a paused
+    // player without metadata still shows up. Returns statuses of players
+    async fn emit_initial_state(
+        &self,
+        connection: &Connection,
+        debounce_sender: &LatestSender<MediaStruct>,
+    ) -> HashMap<String, String> {
+        let players: Vec<(String, String)> = self
+            .players
+            .read()
+            .await
+            .iter()
+            .map(|(unique_name, player_info)| (unique_name.clone(), player_info.bus_name.clone()))
+            .collect();
+
+        // Pinned: only its owner can be shown, None while it isn't running
+        let pinned_owner = self
+            .pinned_player
+            .read()
+            .await
+            .as_ref()
+            .map(|v| v.owner.clone());
+        let mut statuses = HashMap::new();
+        let mut selected: Option<(String, HashMap<String, Value<'static>>)> = None;
+
+        for (unique_name, bus_name) in players {
+            if pinned_owner.is_none() && !self.player_filter.read().await.allows(&bus_name) {
+                continue;
+            }
+
+            let props = match fetch_player_props(connection, &unique_name).await {
+                Ok(props) => props,
+                Err(err) => {
+                    Log::error(
+                        "media_player",
+                        format!("TauriMediaPlayer initial state err: {err}"),
+                    );
+                    continue;
+                }
+            };
+
+            let status = parse_status(&props).unwrap_or_default();
+            let is_playing = status == "Playing";
+            statuses.insert(unique_name.clone(), status);
+            self.update_player_state(&unique_name, &props).await;
+
+            if let Some(owner) = &pinned_owner {
+                if owner.as_deref() != Some(unique_name.as_str()) {
+                    continue;
+                }
+            }
+
+            let selected_playing = selected
+                .as_ref()
+                .is_some_and(|(_, props)| parse_status(props).as_deref() == Some("Playing"));
+            if selected.is_none() || (is_playing && !selected_playing) {
+                selected = Some((unique_name, props));
+            }
+        }
+
+        let (unique_name, props) = match selected {
+            Some(selected) => selected,
+            None if pinned_owner.is_some() => {
+                // Pinned player isn't running, don't keep showing another one:
+                *self.active_player.write().await = None;
+                let mut media_info_struct = self.media_info.write().await;
+                *media_info_struct = MediaStruct::cleared();
+                media_info_struct.any_playing = statuses.values().any(|v| v.as_str() == "Playing");
+                if let Err(err) = debounce_sender.send(media_info_struct.clone()) {
+                    Log::error(
+                        "media_player",
+                        format!("TauriMediaPlayer debounce err: {err}"),
+                    );
+                }
+                return statuses;
+            }
+            None => return statuses,
+        };
+
+        let mut media_info_struct = self.media_info.write().await;
+        *media_info_struct = MediaStruct {
+            any_playing: statuses.values().any(|v| v.as_str() == "Playing"),
+            ..MediaStruct::default()
+        };
+        self.set_player_info(&mut media_info_struct, &unique_name)
+            .await;
+        let config = self.config.read().await.clone();
+        apply_capabilities(&mut media_info_struct.capabilities, &props);
+        apply_properties(&mut media_info_struct, &props, &config);
+        self.update_art(&mut media_info_struct).await;
+        *self.active_player.write().await = Some(unique_name);
+
+        if let Err(err) = debounce_sender.send(media_info_struct.clone()) {
+            Log::error(
+                "media_player",
+                format!("TauriMediaPlayer debounce err: {err}"),
+            );
+        }
+
+        statuses
+    }
+
+    async fn update_players(
+        &self,
+        connection: &Connection,
+        name: &str,
+        old_owner: &str,
+        new_owner: &str,
+    ) {
+        if !old_owner.is_empty() {
+            self.players.write().await.remove(old_owner);
+        }
+
+        if !new_owner.is_empty() {
+            let player_info = fetch_player_info(connection, new_owner, name).await;
+            self.players
+                .write()
+                .await
+                .insert(new_owner.to_string(), player_info);
+        } else {
+            // Player is gone, forget its history:
+            self.status_history.write().await.remove(name);
+        }
+    }
+
+    // Art color and data url are left to the active player, they are costly
+    async fn update_player_state(&self, unique_name: &str, props: &HashMap<String, Value<'_>>) {
+        if !self.players.read().await.contains_key(unique_name) {
+            return;
+        }
+
+        let config = self.config.read().await.clone();
+        let mut player_states = self.player_states.write().await;
+        let player_state = player_states.entry(unique_name.to_string()).or_default();
+        let previous = player_state.clone();
+
+        self.set_player_info(player_state, unique_name).await;
+        apply_properties(player_state, props, &config);
+        apply_capabilities(&mut player_state.capabilities, props);
+
+        if *player_state != previous {
+            let _ = Emit::emit(
+                &self.app_handle,
+                "media_player",
+                PLAYERS_EVENT,
+                sorted_players(&player_states),
+            );
+        }
+    }
+
+    async fn remove_player_state(&self, unique_name: &str) {
+        let mut player_states = self.player_states.write().await;
+        if player_states.remove(unique_name).is_some() {
+            let _ = Emit::emit(
+                &self.app_handle,
+                "media_player",
+                PLAYERS_EVENT,
+                sorted_players(&player_states),
+            );
+        }
+    }
+
+    async fn set_player_info(&self, media_info_struct: &mut MediaStruct, unique_name: &str) {
+        if let Some(player_info) = self.players.read().await.get(unique_name) {
+            media_info_struct.has_player = true;
+            if media_info_struct.bus_name != player_info.bus_name {
+                media_info_struct.instance = player_instance(&player_info.bus_name).to_string();
+                media_info_struct.bus_name = player_info.bus_name.clone();
+                media_info_struct.player_name = player_info.identity.clone();
+                media_info_struct.desktop_entry = player_info.desktop_entry.clone();
+
+                // Rate and Can* of the previous player don't apply anymore:
+                media_info_struct.rate = PlaybackRate::default();
+                media_info_struct.capabilities = MediaCapabilities {
+                    supported_uri_schemes: player_info.supported_uri_schemes.clone(),
+                    supported_mime_types: player_info.supported_mime_types.clone(),
+                    can_raise: player_info.can_raise,
+                    ..MediaCapabilities::default()
+                };
+            }
+        }
+    }
+
+    async fn update_any_playing(
+        &self,
+        any_playing: bool,
+        debounce_sender: &LatestSender<MediaStruct>,
+    ) {
+        let mut media_info_struct = self.media_info.write().await;
+        if media_info_struct.any_playing == any_playing {
+            return;
+        }
+
+        media_info_struct.any_playing = any_playing;
+        if let Err(err) = debounce_sender.send(media_info_struct.clone()) {
+            Log::error(
+                "media_player",
+                format!("TauriMediaPlayer debounce err: {err}"),
+            );
+        }
+    }
+
+    async fn update_art(&self, media_info_struct: &mut MediaStruct) {
+        self.update_art_color(media_info_struct).await;
+        self.update_art_data_url(media_info_struct).await;
+        self.update_art_size(media_info_struct).await;
+    }
+
+    // Remote art isn't downloaded, its size stays unknown
+    async fn update_art_size(&self, media_info_struct: &mut MediaStruct) {
+        let art_url = media_info_struct.art_url.clone();
+        let art_path = media_info_struct.art_path.clone();
+        if art_path.is_none() && media_info_struct.art_source != ArtSource::Data {
+            (media_info_struct.art_width, media_info_struct.art_height) = (None, None);
+            return;
+        }
+
+        let cached = match self.art_size.lock().unwrap().as_ref() {
+            Some((cached_url, size)) if *cached_url == art_url => Some(*size),
+            _ => None,
+        };
+        let size = match cached {
+            Some(size) => size,
+            None => {
+                let url = art_url.clone();
+                let size = tokio::task::spawn_blocking(move || match art_path {
+                    Some(art_path) => art::dimensions(Path::new(&art_path)),
+                    None => art::data_url_dimensions(&url),
+                })
+                .await
+                .unwrap_or_default();
+                *self.art_size.lock().unwrap() = Some((art_url, size));
+                size
+            }
+        };
+
+        media_info_struct.art_width = size.map(|(width, _)| width);
+        media_info_struct.art_height = size.map(|(_, height)| height);
+    }
+
+    async fn update_art_data_url(&self, media_info_struct: &mut MediaStruct) {
+        let art_path = match &media_info_struct.art_path {
+            Some(art_path) if self.config.read().await.inline_art => art_path.clone(),
+            _ => {
+                media_info_struct.art_data_url = None;
+                return;
+            }
+        };
+
+        let art_url = media_info_struct.art_url.clone();
+        if let Some((cached_url, data_url)) = self.art_data_url.lock().unwrap().as_ref() {
+            if *cached_url == art_url {
+                media_info_struct.art_data_url = data_url.clone();
+                return;
+            }
+        }
+
+        let data_url = tokio::task::spawn_blocking(move || art::data_url(Path::new(&art_path)))
+            .await
+            .unwrap_or_default();
+
+        *self.art_data_url.lock().unwrap() = Some((art_url, data_url.clone()));
+        media_info_struct.art_data_url = data_url;
+    }
+
+    async fn update_art_color(&self, media_info_struct: &mut MediaStruct) {
+        // Only local art, remote urls aren't downloaded:
+        let art_path = match &media_info_struct.art_path {
+            Some(art_path) => art_path.clone(),
+            None => {
+                media_info_struct.art_color = None;
+                return;
+            }
+        };
+
+        let art_url = media_info_struct.art_url.clone();
+        if let Some(art_color) = self.art_colors.lock().unwrap().get(&art_url) {
+            media_info_struct.art_color = art_color.clone();
+            return;
+        }
+
+        // Decoding is slow, keep it off the event loop:
+        let art_color =
+            tokio::task::spawn_blocking(move || art::average_color(Path::new(&art_path)))
+                .await
+                .unwrap_or_default();
+
+        let mut art_colors = self.art_colors.lock().unwrap();
+        if art_colors.len() >= 64 {
+            art_colors.clear();
+        }
+        art_colors.insert(art_url, art_color.clone());
+        media_info_struct.art_color = art_color;
+    }
+
+    async fn record_status(&self, sender: Option<&str>, status: &str) {
+        let history_size = self.config.read().await.status_history_size;
+        if history_size == 0 {
+            return;
+        }
+
+        let bus_name = match sender {
+            Some(sender) => self
+                .players
+                .read()
+                .await
+                .get(sender)
+                .map(|v| v.bus_name.clone()),
+            None => None,
+        };
+        let bus_name = match bus_name {
+            Some(bus_name) => bus_name,
+            None => return,
+        };
+
+        let mut status_history = self.status_history.write().await;
+        let history = status_history.entry(bus_name).or_default();
+
+        // Only transitions, players often repeat the same status:
+        if history.back().map(|v| v.status.as_str()) == Some(status) {
+            return;
+        }
+
+        history.push_back(StatusTransition {
+            status: status.to_string(),
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|v| v.as_millis())
+                .unwrap_or_default(),
+        });
+
+        while history.len() > history_size {
+            history.pop_front();
+        }
+    }
+
+    async fn is_pinned_sender(&self, dbus_proxy: &DBusProxy<'_>, sender: Option<&str>) -> bool {
+        let mut pinned_player = self.pinned_player.write().await;
+        let pinned = match pinned_player.as_mut() {
+            Some(pinned) => pinned,
+            None => return true,
+        };
+
+        if !pinned.resolved {
+            pinned.owner = name_owner(dbus_proxy, &pinned.bus_name).await;
+            pinned.resolved = true;
+        }
+
+        pinned.owner.is_some() && pinned.owner.as_deref() == sender
+    }
+
+    async fn is_allowed_sender(&self, sender: Option<&str>) -> bool {
+        if self.pinned_player.read().await.is_some() {
+            return true;
+        }
+
+        let players = self.players.read().await;
+        match sender.and_then(|v| players.get(v)) {
+            Some(player_info) => self
+                .player_filter
+                .read()
+                .await
+                .allows(&player_info.bus_name),
+            None => true,
+        }
+    }
+
+    // Returns true when pinned player is gone and state must be cleared
+    async fn update_pinned_owner(&self, name: &str, new_owner: &str) -> bool {
+        let mut pinned_player = self.pinned_player.write().await;
+        match pinned_player.as_mut() {
+            Some(pinned) if pinned.bus_name == name => {
+                // Keep the pin, it re-applies when the player returns:
+                pinned.owner = (!new_owner.is_empty()).then(|| new_owner.to_string());
+                pinned.resolved = true;
+                pinned.owner.is_none()
+            }
+            _ => false,
+        }
+    }
+}
+
+fn sorted_players(player_states: &HashMap<String, MediaStruct>) -> Vec<MediaStruct> {
+    let mut players: Vec<MediaStruct> = player_states.values().cloned().collect();
+    players.sort_by(|a, b| a.bus_name.cmp(&b.bus_name));
+    players
+}
+
+// Instances of the same app differ by suffix after the app name,
+// e.g. org.mpris.MediaPlayer2.firefox.instance_1_42 -> instance_1_42
+fn player_instance(bus_name: &str) -> &str {
+    let app_and_instance = bus_name
+        .strip_prefix("org.mpris.MediaPlayer2.")
+        .unwrap_or_default();
+
+    match app_and_instance.split_once('.') {
+        Some((_, instance)) => instance,
+        None => "",
+    }
+}
+
+fn parse_status(props: &HashMap<String, Value>) -> Option<String> {
+    let playing_status = match props.get("PlaybackStatus") {
+        Some(v) => v.to_string(),
+        None => String::new(),
+    };
+
+    (!playing_status.is_empty()).then(|| playing_status.replace("\"", ""))
+}
+
+// Update media struct from Player properties, returns true if anything changed
+fn apply_properties(
+    media_info_struct: &mut MediaStruct,
+    props: &HashMap<String, Value>,
+    config: &MediaPlayerConfig,
+) -> bool {
+    let mut updated = false;
+
+    // Getting is Play state:
+    if let Some(playing_status) = parse_status(props) {
+        media_info_struct.status = playing_status;
+        updated = true;
+    }
+
+    if let Some(Value::Bool(shuffle)) = props.get("Shuffle") {
+        media_info_struct.shuffle = *shuffle;
+        updated = true;
+    }
+
+    if let Some(Value::Str(loop_status)) = props.get("LoopStatus") {
+        media_info_struct.loop_status = loop_status.to_string();
+        updated = true;
+    }
+
+    if let Some(Value::F64(volume)) = props.get("Volume") {
+        media_info_struct.volume = *volume;
+        updated = true;
+    }
+
+    if let Some(position) = integer(props.get("Position")) {
+        media_info_struct.position_us = position;
+        updated = true;
+    }
+
+    if let Some(Value::F64(rate)) = props.get("Rate") {
+        media_info_struct.rate = PlaybackRate(*rate);
+        updated = true;
+    }
+
+    // Getting metadata:
+    if let Some(Value::Dict(metadata)) = props.get("Metadata") {
+        apply_metadata(media_info_struct, metadata, config);
+        updated = true;
+    }
+
+    if updated {
+        update_length(media_info_struct, config);
+        update_now_playing_line(media_info_struct, config);
+    }
+
+    updated
+}
+
+// Recomputed on position changes too, the unit guess depends on position
+fn update_length(media_info_struct: &mut MediaStruct, config: &MediaPlayerConfig) {
+    media_info_struct.length_us = length::length_us(
+        media_info_struct.length_raw,
+        media_info_struct.position_us,
+        config
+            .length_units
+            .get(&media_info_struct.bus_name)
+            .copied(),
+        config.guess_length_unit,
+    );
+}
+
+fn update_now_playing_line(media_info_struct: &mut MediaStruct, config: &MediaPlayerConfig) {
+    let line = template::render(&config.now_playing_template, |name| match name {
+        "title" => Some(media_info_struct.title_full.clone()),
+        "artist" => Some(media_info_struct.artist_full.join(", ")),
+        "album" => Some(media_info_struct.album_full.clone()),
+        "status" => Some(media_info_struct.status.clone()),
+        _ => None,
+    });
+    media_info_struct.now_playing_line = truncate(&line, config.max_text_length);
+}
+
+// mpris:trackid of Metadata, an object path, or a string for some players
+fn track_id(props: &HashMap<String, Value>) -> Option<String> {
+    match props.get("Metadata") {
+        Some(Value::Dict(metadata)) if has_string_keys(metadata) => metadata_track_id(metadata),
+        _ => None,
+    }
+}
+
+fn metadata_track_id(metadata: &Dict) -> Option<String> {
+    match metadata.get::<str, Value>("mpris:trackid") {
+        Ok(Some(Value::ObjectPath(v))) => Some(v.to_string()),
+        Ok(Some(Value::Str(v))) => Some(v.to_string()),
+        _ => None,
+    }
+}
+
+fn only_position_changed(previous: &MediaStruct, current: &MediaStruct) -> bool {
+    previous.position_us != current.position_us
+        && MediaStruct {
+            position_us: current.position_us,
+            ..previous.clone()
+        } == *current
+}
+
+// Gapless next track: trackid changed while Playing, before and after
+fn is_track_change(
+    previous: Option<&str>,
+    track_id: &str,
+    status: &str,
+    new_status: Option<&str>,
+) -> bool {
+    let still_playing = status == "Playing" && new_status.is_none_or(|v| v == "Playing");
+    still_playing && previous.is_some_and(|v| v != track_id)
+}
+
+// Transient sounds (chimes played through a browser) show at least two of:
+// a length up to transient_max_length_ms, no title and no artist, and a
+// stop within transient_stop_ms of starting to play. One alone is common
+// for real tracks and streams. A 0 threshold disables its signal
+fn is_transient(
+    media_info_struct: &MediaStruct,
+    stopped_quickly: bool,
+    config: &MediaPlayerConfig,
+) -> bool {
+    let max_length_us = i64::try_from(config.transient_max_length_ms)
+        .unwrap_or(i64::MAX)
+        .saturating_mul(1000);
+    let short = media_info_struct.length_us > 0 && media_info_struct.length_us <= max_length_us;
+    let untitled =
+        media_info_struct.title_full.is_empty() && media_info_struct.artist_full.is_empty();
+    let stopped_quickly = stopped_quickly && config.transient_stop_ms > 0;
+
+    [short, untitled, stopped_quickly]
+        .into_iter()
+        .filter(|v| *v)
+        .count()
+        >= 2
+}
+
+// Returns true if any Can* property changed
+fn apply_capabilities(
+    capabilities: &mut MediaCapabilities,
+    props: &HashMap<String, Value>,
+) -> bool {
+    let mut changed = false;
+
+    for (key, capability) in [
+        ("CanGoNext", &mut capabilities.can_go_next),
+        ("CanGoPrevious", &mut capabilities.can_go_previous),
+        ("CanPlay", &mut capabilities.can_play),
+        ("CanPause", &mut capabilities.can_pause),
+        ("CanSeek", &mut capabilities.can_seek),
+        ("CanControl", &mut capabilities.can_control),
+    ] {
+        if let Some(Value::Bool(value)) = props.get(key) {
+            changed |= *capability != *value;
+            *capability = *value;
+        }
+    }
+
+    changed
+}
+
+fn apply_metadata(
+    media_info_struct: &mut MediaStruct,
+    metadata: &Dict,
+    config: &MediaPlayerConfig,
+) {
+    // Metadata must be keyed by strings (a{sv}), otherwise treat it as empty:
+    let empty_metadata = Dict::new(
+        Signature::from_static_str_unchecked("s"),
+        Signature::from_static_str_unchecked("v"),
+    );
+    let metadata = if has_string_keys(metadata) {
+        metadata
+    } else {
+        Log::warn(
+            "media_player",
+            format!(
+                "TauriMediaPlayer metadata warn: unexpected signature {}",
+                metadata.full_signature()
+            ),
+        );
+        &empty_metadata
+    };
+
+    media_info_struct.title = metadata_string(metadata, "xesam:title");
+    media_info_struct.album = metadata_string(metadata, "xesam:album");
+    media_info_struct.art_url = metadata_string(metadata, "mpris:artUrl");
+    media_info_struct.art_path = art_path(&media_info_struct.art_url);
+    media_info_struct.art_source = ArtSource::from_url(&media_info_struct.art_url);
+
+    // List by the spec, some players send a single string:
+    media_info_struct.artist = match metadata.get::<str, Value>("xesam:artist") {
+        Ok(Some(Value::Str(v))) if !v.is_empty() => vec![v.to_string()],
+        Ok(value) => string_list(value),
+        Err(_) => Vec::new(),
+    };
+    if media_info_struct.artist.is_empty() {
+        media_info_struct.artist = fallback_artist(metadata, &config.artist_fallback_keys);
+    }
+
+    media_info_struct.title_raw = String::new();
+    media_info_struct.artist_raw = Vec::new();
+    media_info_struct.album_raw = String::new();
+    if config.dedup_artists || config.strip_control_chars {
+        media_info_struct.artist_raw = media_info_struct.artist.clone();
+    }
+    if config.strip_control_chars {
+        media_info_struct.title_raw = std::mem::take(&mut media_info_struct.title);
+        media_info_struct.title = strip_control_chars(&media_info_struct.title_raw);
+        media_info_struct.album_raw = std::mem::take(&mut media_info_struct.album);
+        media_info_struct.album = strip_control_chars(&media_info_struct.album_raw);
+        media_info_struct.artist = media_info_struct
+            .artist
+            .iter()
+            .map(|v| strip_control_chars(v))
+            .collect();
+    }
+    if config.dedup_artists {
+        media_info_struct.artist = dedup(&media_info_struct.artist);
+    }
+    media_info_struct.has_metadata = has_any_metadata(metadata);
+
+    media_info_struct.track_id = metadata_track_id(metadata).unwrap_or_default();
+    media_info_struct.length_raw =
+        integer(metadata.get("mpris:length").unwrap_or_default()).unwrap_or_default();
+
+    media_info_struct.audio_bitrate =
+        integer(metadata.get("xesam:audioBitrate").unwrap_or_default());
+    media_info_struct.audio_bpm = integer(metadata.get("xesam:audioBPM").unwrap_or_default());
+
+    media_info_struct.title_full = media_info_struct.title.clone();
+    media_info_struct.artist_full = media_info_struct.artist.clone();
+    media_info_struct.album_full = media_info_struct.album.clone();
+    truncate_text(media_info_struct, config.max_text_length);
+}
+
+// Control characters break panel rendering, whitespace ones are kept
+fn strip_control_chars(text: &str) -> String {
+    text.chars()
+        .filter(|c| !c.is_control() || matches!(c, '\t' | '\n' | '\r'))
+        .collect()
+}
+
+// Drop repeated entries, keeping the first occurrence order
+fn dedup(values: &[String]) -> Vec<String> {
+    let mut seen = HashSet::new();
+    values
+        .iter()
+        .filter(|v| seen.insert(v.as_str()))
+        .cloned()
+        .collect()
+}
+
+// Shorten displayed texts from *_full values, 0 keeps them as is
+fn truncate_text(media_info_struct: &mut MediaStruct, max_length: usize) {
+    media_info_struct.title = truncate(&media_info_struct.title_full, max_length);
+    media_info_struct.album = truncate(&media_info_struct.album_full, max_length);
+    media_info_struct.artist = media_info_struct
+        .artist_full
+        .iter()
+        .map(|v| truncate(v, max_length))
+        .collect();
+}
+
+// Cut on grapheme boundaries, so multibyte characters stay whole
+fn truncate(text: &str, max_length: usize) -> String {
+    if max_length == 0 || text.graphemes(true).count() <= max_length {
+        return text.to_string();
+    }
+
+    let mut truncated: String = text.graphemes(true).take(max_length - 1).collect();
+    truncated.push('…');
+    truncated
+}
+
+// Local path of file:///.. or file://localhost/.. art url, percent-decoded
+fn art_path(art_url: &str) -> Option<String> {
+    // Some players send a plain path:
+    if art_url.starts_with('/') {
+        return Some(art_url.to_string());
+    }
+
+    let path = art_url.strip_prefix("file://")?;
+    let path = path.strip_prefix("localhost").unwrap_or(path);
+    // Other hosts aren't local files:
+    if !path.starts_with('/') {
+        return None;
+    }
+
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    percent_decode_str(path)
+        .decode_utf8()
+        .ok()
+        .map(|v| v.into_owned())
+}
+
+// Minimal players omit keys, missing or mistyped ones are empty
+fn metadata_string(metadata: &Dict, key: &str) -> String {
+    match metadata.get::<str, Value>(key) {
+        Ok(Some(Value::Str(v))) => v.to_string(),
+        _ => String::new(),
+    }
+}
+
+// Buggy players use these keys with either a list or a single string
+fn fallback_artist(metadata: &Dict, keys: &[String]) -> Vec<String> {
+    for key in keys {
+        let artist = match metadata.get::<str, Value>(key.as_str()) {
+            Ok(Some(Value::Str(v))) if !v.is_empty() => vec![v.to_string()],
+            Ok(value) => string_list(value),
+            Err(_) => Vec::new(),
+        };
+
+        if !artist.is_empty() {
+            return artist;
+        }
+    }
+
+    Vec::new()
+}
+
+// What woke the listen loop up
+enum Wake<T> {
+    Message(Option<T>),
+    // Deadline of the pending player switch passed:
+    Switch,
+    Shutdown,
+}
+
+async fn next_event<S: Stream + Unpin>(
+    stream: &mut S,
+    shutdown: &CancellationToken,
+    deadline: Option<Instant>,
+) -> Wake<S::Item> {
+    let switch = async {
+        match deadline {
+            Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+            None => std::future::pending().await,
+        }
+    };
+
+    tokio::select! {
+        _ = shutdown.cancelled() => Wake::Shutdown,
+        event_message = stream.next() => Wake::Message(event_message),
+        _ = switch => Wake::Switch,
+    }
+}
+
+// The only player in Playing state, if there is exactly one
+fn sole_playing(statuses: &HashMap<String, String>) -> Option<&str> {
+    let mut playing = statuses
+        .iter()
+        .filter(|(_, status)| status.as_str() == "Playing")
+        .map(|(name, _)| name.as_str());
+
+    match (playing.next(), playing.next()) {
+        (Some(name), None) => Some(name),
+        _ => None,
+    }
+}
+
+// Players don't agree on integer types, accept any of them
+fn integer(value: Option<&Value>) -> Option<i64> {
+    match value {
+        Some(Value::U8(v)) => Some(i64::from(*v)),
+        Some(Value::I16(v)) => Some(i64::from(*v)),
+        Some(Value::U16(v)) => Some(i64::from(*v)),
+        Some(Value::I32(v)) => Some(i64::from(*v)),
+        Some(Value::U32(v)) => Some(i64::from(*v)),
+        Some(Value::I64(v)) => Some(*v),
+        Some(Value::U64(v)) => i64::try_from(*v).ok(),
+        Some(Value::F64(v)) => Some(*v as i64),
+        Some(Value::Value(v)) => integer(Some(v)),
+        _ => None,
+    }
+}
+
+fn string_list(value: Option<&Value>) -> Vec<String> {
+    match value {
+        Some(Value::Array(arr)) => arr
+            .iter()
+            .filter_map(|v| v.downcast_ref::<str>().map(|a| a.to_string()))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+async fn fetch_props(
+    connection: &Connection,
+    name: &str,
+    interface: &'static str,
+) -> zbus::Result<HashMap<String, Value<'static>>> {
+    let proxy = PropertiesProxy::builder(connection)
+        .destination(name.to_string())?
+        .path("/org/mpris/MediaPlayer2")?
+        .build()
+        .await?;
+
+    let props = proxy
+        .get_all(InterfaceName::from_static_str_unchecked(interface))
+        .await?;
+
+    Ok(props.into_iter().map(|(k, v)| (k, v.into())).collect())
+}
+
+async fn fetch_player_props(
+    connection: &Connection,
+    name: &str,
+) -> zbus::Result<HashMap<String, Value<'static>>> {
+    fetch_props(connection, name, "org.mpris.MediaPlayer2.Player").await
+}
+
+async fn fetch_volume(connection: &Connection, name: &str) -> Option<f64> {
+    let proxy = PropertiesProxy::builder(connection)
+        .destination(name.to_string())
+        .ok()?
+        .path("/org/mpris/MediaPlayer2")
+        .ok()?
+        .build()
+        .await
+        .ok()?;
+
+    let volume = proxy
+        .get(
+            InterfaceName::from_static_str_unchecked("org.mpris.MediaPlayer2.Player"),
+            "Volume",
+        )
+        .await
+        .ok()?;
+
+    volume.downcast_ref::<f64>().copied()
+}
+
+// Unique name owning a well-known name, None when nobody owns it
+async fn name_owner(dbus_proxy: &DBusProxy<'_>, bus_name: &str) -> Option<String> {
+    let name = BusName::try_from(bus_name).ok()?;
+    dbus_proxy
+        .get_name_owner(name)
+        .await
+        .ok()
+        .map(|v| v.to_string())
+}
+
+// Player without root interface still works, just without its info.
+// Fetched once when the player appears, root properties don't change
+async fn fetch_player_info(
+    connection: &Connection,
+    unique_name: &str,
+    bus_name: &str,
+) -> PlayerInfo {
+    let props = fetch_props(connection, unique_name, "org.mpris.MediaPlayer2")
+        .await
+        .unwrap_or_default();
+
+    PlayerInfo {
+        bus_name: bus_name.to_string(),
+        identity: props
+            .get("Identity")
+            .and_then(|v| v.downcast_ref::<str>())
+            .unwrap_or_default()
+            .to_string(),
+        desktop_entry: props
+            .get("DesktopEntry")
+            .and_then(|v| v.downcast_ref::<str>())
+            .unwrap_or_default()
+            .to_string(),
+        supported_uri_schemes: string_list(props.get("SupportedUriSchemes")),
+        supported_mime_types: string_list(props.get("SupportedMimeTypes")),
+        can_raise: matches!(props.get("CanRaise"), Some(Value::Bool(true))),
+    }
+}
+
+fn has_string_keys(dict: &Dict) -> bool {
+    // Dict signature looks like "a{sv}", key type is the third char:
+    dict.full_signature().as_str().starts_with("a{s")
+}
+
+fn has_any_metadata(metadata: &Dict) -> bool {
+    let metadata = match HashMap::<String, OwnedValue>::try_from(metadata.clone()) {
+        Ok(metadata) => metadata,
+        Err(_) => return false,
+    };
+
+    metadata.iter().any(|(key, value)| match &**value {
+        // Players without track send this instead of empty metadata:
+        Value::ObjectPath(v) if key == "mpris:trackid" => {
+            v.as_str() != "/org/mpris/MediaPlayer2/TrackList/NoTrack"
+        }
+        Value::Str(v) => !v.is_empty(),
+        Value::Array(v) => !v.is_empty(),
+        _ => true,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncate_keeps_grapheme_clusters() {
+        // Family emoji and "e" with combining acute accent are one grapheme each:
+        let text = "👨‍👩‍👧e\u{301}xyz";
+        assert_eq!(truncate(text, 3), "👨‍👩‍👧e\u{301}…");
+        assert_eq!(truncate("日本語のタイトル", 4), "日本語…");
+    }
+
+    #[test]
+    fn truncate_edge_lengths() {
+        assert_eq!(truncate("Title", 0), "Title");
+        assert_eq!(truncate("Title", 1), "…");
+        assert_eq!(truncate("", 1), "");
+        // Exactly at the limit stays as is, one over is cut:
+        assert_eq!(truncate("Title", 5), "Title");
+        assert_eq!(truncate("Titles", 5), "Titl…");
+    }
+
+    #[test]
+    fn truncate_text_cuts_shown_fields_only() {
+        let mut media_info = MediaStruct {
+            title_full: "Long title".to_string(),
+            album_full: "Album".to_string(),
+            artist_full: vec!["First artist".to_string(), "Two".to_string()],
+            ..MediaStruct::default()
+        };
+        truncate_text(&mut media_info, 5);
+
+        assert_eq!(media_info.title, "Long…");
+        assert_eq!(media_info.album, "Album");
+        assert_eq!(media_info.artist, ["Firs…", "Two"]);
+        assert_eq!(media_info.title_full, "Long title");
+    }
+
+    fn dict<'a>(key_signature: &'static str, entries: Vec<(Value<'a>, Value<'a>)>) -> Dict<'a, 'a> {
+        let mut dict = Dict::new(
+            Signature::from_static_str_unchecked(key_signature),
+            Signature::from_static_str_unchecked("v"),
+        );
+        for (key, value) in entries {
+            dict.append(key, Value::Value(Box::new(value))).unwrap();
+        }
+        dict
+    }
+
+    #[test]
+    fn string_keys_are_detected() {
+        let metadata = dict("s", vec![(Value::from("xesam:title"), Value::from("Song"))]);
+        assert!(has_string_keys(&metadata));
+        assert!(!has_string_keys(&dict(
+            "i",
+            vec![(Value::I32(1), Value::from("Song"))]
+        )));
+        assert!(!has_string_keys(&dict("o", Vec::new())));
+    }
+
+    #[test]
+    fn integer_keyed_metadata_is_empty() {
+        let metadata = dict(
+            "i",
+            vec![
+                (Value::I32(1), Value::from("Song")),
+                (Value::I32(2), Value::I64(180_000_000)),
+            ],
+        );
+        let mut media_info = MediaStruct {
+            title: "Previous".to_string(),
+            ..MediaStruct::default()
+        };
+        apply_metadata(&mut media_info, &metadata, &MediaPlayerConfig::default());
+
+        assert_eq!(media_info.title, "");
+        assert!(!media_info.has_metadata);
+        assert_eq!(media_info.length_raw, 0);
+    }
+
+    fn metadata(entries: Vec<(&str, Value<'static>)>) -> Dict<'static, 'static> {
+        let entries = entries
+            .into_iter()
+            .map(|(key, value)| (Value::from(key.to_string()), value))
+            .collect();
+        dict("s", entries)
+    }
+
+    fn applied(metadata: &Dict, config: &MediaPlayerConfig) -> MediaStruct {
+        let mut media_info = MediaStruct::default();
+        apply_metadata(&mut media_info, metadata, config);
+        media_info
+    }
+
+    #[test]
+    fn stream_with_only_url_has_metadata() {
+        let stream = metadata(vec![(
+            "xesam:url",
+            Value::from("https://radio.example/stream".to_string()),
+        )]);
+        let media_info = applied(&stream, &MediaPlayerConfig::default());
+
+        assert!(media_info.has_metadata);
+        assert_eq!(media_info.title, "");
+    }
+
+    #[test]
+    fn empty_values_are_no_metadata() {
+        assert!(!has_any_metadata(&metadata(Vec::new())));
+        assert!(!has_any_metadata(&metadata(vec![
+            ("xesam:title", Value::from(String::new())),
+            ("xesam:artist", Value::from(Vec::<String>::new())),
+        ])));
+        let no_track =
+            ObjectPath::from_static_str_unchecked("/org/mpris/MediaPlayer2/TrackList/NoTrack");
+        assert!(!has_any_metadata(&metadata(vec![(
+            "mpris:trackid",
+            Value::from(no_track)
+        )])));
+    }
+
+    #[test]
+    fn artist_from_fallback_key() {
+        let config = MediaPlayerConfig::default();
+        let buggy = metadata(vec![
+            ("xesam:title", Value::from("Song".to_string())),
+            ("albumartist", Value::from("Fallback".to_string())),
+        ]);
+        assert_eq!(applied(&buggy, &config).artist, ["Fallback"]);
+
+        // Earlier keys of the list win, lists work too:
+        let buggy = metadata(vec![
+            ("artist", Value::from("Later".to_string())),
+            (
+                "mpris:artist",
+                Value::from(vec!["First".to_string(), "Second".to_string()]),
+            ),
+        ]);
+        assert_eq!(applied(&buggy, &config).artist, ["First", "Second"]);
+    }
+
+    #[test]
+    fn standard_artist_key_stays_primary() {
+        let config = MediaPlayerConfig::default();
+        let both = metadata(vec![
+            ("xesam:artist", Value::from(vec!["Standard".to_string()])),
+            ("mpris:artist", Value::from("Fallback".to_string())),
+        ]);
+        assert_eq!(applied(&both, &config).artist, ["Standard"]);
+
+        // Empty standard value falls back, an empty key list doesn't:
+        let empty = metadata(vec![
+            ("xesam:artist", Value::from(Vec::<String>::new())),
+            ("mpris:artist", Value::from("Fallback".to_string())),
+        ]);
+        assert_eq!(applied(&empty, &config).artist, ["Fallback"]);
+        let config = MediaPlayerConfig {
+            artist_fallback_keys: Vec::new(),
+            ..config
+        };
+        assert!(applied(&empty, &config).artist.is_empty());
+    }
+
+    #[test]
+    fn art_path_decodes_file_urls() {
+        assert_eq!(
+            art_path("file:///home/me/My%20Music/cover.jpg").as_deref(),
+            Some("/home/me/My Music/cover.jpg")
+        );
+        assert_eq!(
+            art_path("file:///tmp/Bj%C3%B6rk%20-%20Caf%C3%A9.png").as_deref(),
+            Some("/tmp/Björk - Café.png")
+        );
+        // Already decoded unicode is kept:
+        assert_eq!(
+            art_path("file:///tmp/Café.png").as_deref(),
+            Some("/tmp/Café.png")
+        );
+    }
+
+    #[test]
+    fn art_path_url_forms() {
+        assert_eq!(
+            art_path("file://localhost/tmp/a.png").as_deref(),
+            Some("/tmp/a.png")
+        );
+        assert_eq!(
+            art_path("/tmp/plain.png").as_deref(),
+            Some("/tmp/plain.png")
+        );
+        assert_eq!(
+            art_path("file:///tmp/a.png?size=large#x").as_deref(),
+            Some("/tmp/a.png")
+        );
+        assert_eq!(art_path("file://otherhost/tmp/a.png"), None);
+        assert_eq!(art_path("https://example.com/a.png"), None);
+        assert_eq!(art_path(""), None);
+        // Invalid UTF-8 after decoding:
+        assert_eq!(art_path("file:///tmp/%FF.png"), None);
+    }
+
+    #[test]
+    fn paused_player_without_metadata_keeps_status() {
+        let props = HashMap::from([
+            ("PlaybackStatus".to_string(), Value::from("Paused")),
+            ("Metadata".to_string(), Value::Dict(metadata(Vec::new()))),
+        ]);
+        let mut media_info = MediaStruct {
+            has_player: true,
+            ..MediaStruct::default()
+        };
+
+        assert!(apply_properties(
+            &mut media_info,
+            &props,
+            &MediaPlayerConfig::default()
+        ));
+        assert_eq!(media_info.status, "Paused");
+        assert!(media_info.has_player);
+        assert!(!media_info.has_metadata);
+    }
+
+    #[test]
+    fn status_is_read_without_quotes() {
+        let props = HashMap::from([("PlaybackStatus".to_string(), Value::from("Playing"))]);
+        assert_eq!(parse_status(&props).as_deref(), Some("Playing"));
+        assert_eq!(parse_status(&HashMap::new()), None);
+    }
+
+    fn artists(values: &[&str]) -> Value<'static> {
+        Value::from(values.iter().map(|v| v.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn dedup_keeps_first_occurrence_order() {
+        let values: Vec<String> = ["B", "A", "B", "C", "A"].map(String::from).to_vec();
+        assert_eq!(dedup(&values), ["B", "A", "C"]);
+        assert!(dedup(&[]).is_empty());
+        // Case differs, so these aren't duplicates:
+        let values: Vec<String> = ["a", "A"].map(String::from).to_vec();
+        assert_eq!(dedup(&values), ["a", "A"]);
+    }
+
+    #[test]
+    fn dedup_artists_keeps_raw_list() {
+        let repeated = metadata(vec![("xesam:artist", artists(&["Main", "Feat", "Main"]))]);
+
+        let media_info = applied(&repeated, &MediaPlayerConfig::default());
+        assert_eq!(media_info.artist, ["Main", "Feat", "Main"]);
+        assert!(media_info.artist_raw.is_empty());
+
+        let config = MediaPlayerConfig {
+            dedup_artists: true,
+            ..MediaPlayerConfig::default()
+        };
+        let media_info = applied(&repeated, &config);
+        assert_eq!(media_info.artist, ["Main", "Feat"]);
+        assert_eq!(media_info.artist_full, ["Main", "Feat"]);
+        assert_eq!(media_info.artist_raw, ["Main", "Feat", "Main"]);
+    }
+
+    #[test]
+    fn consecutive_tracks_while_playing_are_a_track_change() {
+        let first = "/org/mpris/MediaPlayer2/Track/1";
+        let second = "/org/mpris/MediaPlayer2/Track/2";
+
+        assert!(is_track_change(Some(first), second, "Playing", None));
+        assert!(is_track_change(
+            Some(first),
+            second,
+            "Playing",
+            Some("Playing")
+        ));
+        // Same track, or the first one seen:
+        assert!(!is_track_change(Some(first), first, "Playing", None));
+        assert!(!is_track_change(None, first, "Playing", None));
+    }
+
+    #[test]
+    fn track_change_with_status_change_is_not_gapless() {
+        let (first, second) = ("/track/1", "/track/2");
+        assert!(!is_track_change(Some(first), second, "Paused", None));
+        assert!(!is_track_change(
+            Some(first),
+            second,
+            "Playing",
+            Some("Paused")
+        ));
+        assert!(!is_track_change(
+            Some(first),
+            second,
+            "Stopped",
+            Some("Playing")
+        ));
+    }
+
+    #[test]
+    fn art_source_by_url() {
+        assert_eq!(ArtSource::from_url(""), ArtSource::None);
+        assert_eq!(ArtSource::from_url("file:///tmp/a.png"), ArtSource::File);
+        assert_eq!(ArtSource::from_url("/tmp/a.png"), ArtSource::File);
+        assert_eq!(
+            ArtSource::from_url("data:image/png;base64,AAAA"),
+            ArtSource::Data
+        );
+        assert_eq!(
+            ArtSource::from_url("https://i.scdn.co/image/ab67"),
+            ArtSource::Remote
+        );
+        assert_eq!(ArtSource::from_url("http://cover"), ArtSource::Remote);
+        // Neither url nor absolute path:
+        assert_eq!(ArtSource::from_url("cover.png"), ArtSource::None);
+    }
+
+    #[test]
+    fn art_source_follows_metadata() {
+        let config = MediaPlayerConfig::default();
+        let remote = metadata(vec![(
+            "mpris:artUrl",
+            Value::from("https://example.com/a.jpg".to_string()),
+        )]);
+        let media_info = applied(&remote, &config);
+        assert_eq!(media_info.art_source, ArtSource::Remote);
+        assert_eq!(media_info.art_path, None);
+
+        let media_info = applied(&metadata(Vec::new()), &config);
+        assert_eq!(media_info.art_source, ArtSource::None);
+    }
+
+    #[test]
+    fn strip_control_chars_keeps_whitespace() {
+        assert_eq!(
+            strip_control_chars("Song\u{0}\u{1b}[0m Title"),
+            "Song[0m Title"
+        );
+        assert_eq!(strip_control_chars("Line\tone\r\ntwo"), "Line\tone\r\ntwo");
+        assert_eq!(strip_control_chars("\u{7f}\u{85}Café"), "Café");
+        assert_eq!(strip_control_chars(""), "");
+    }
+
+    #[test]
+    fn control_chars_are_kept_by_default() {
+        let dirty = metadata(vec![("xesam:title", Value::from("A\u{7}B".to_string()))]);
+        let media_info = applied(&dirty, &MediaPlayerConfig::default());
+
+        assert_eq!(media_info.title, "A\u{7}B");
+        assert_eq!(media_info.title_raw, "");
+    }
+
+    #[test]
+    fn stripped_metadata_keeps_raw_values() {
+        let config = MediaPlayerConfig {
+            strip_control_chars: true,
+            ..MediaPlayerConfig::default()
+        };
+        let dirty = metadata(vec![
+            ("xesam:title", Value::from("Ti\u{0}tle".to_string())),
+            ("xesam:album", Value::from("Al\u{1b}bum".to_string())),
+            ("xesam:artist", artists(&["Art\u{8}ist", "Two"])),
+        ]);
+        let media_info = applied(&dirty, &config);
+
+        assert_eq!(media_info.title, "Title");
+        assert_eq!(media_info.album, "Album");
+        assert_eq!(media_info.artist, ["Artist", "Two"]);
+        assert_eq!(media_info.title_full, "Title");
+        assert_eq!(media_info.title_raw, "Ti\u{0}tle");
+        assert_eq!(media_info.album_raw, "Al\u{1b}bum");
+        assert_eq!(media_info.artist_raw, ["Art\u{8}ist", "Two"]);
+    }
+
+    fn blip(length_us: i64, title: &str) -> MediaStruct {
+        MediaStruct {
+            length_us,
+            title_full: title.to_string(),
+            ..MediaStruct::default()
+        }
+    }
+
+    #[test]
+    fn untitled_second_long_blip_is_transient() {
+        let config = MediaPlayerConfig::default();
+        assert!(is_transient(&blip(1_000_000, ""), false, &config));
+        assert!(is_transient(&blip(1_000_000, ""), true, &config));
+    }
+
+    #[test]
+    fn transient_needs_two_of_three_signals() {
+        let config = MediaPlayerConfig::default();
+        // Short and stopped quickly, but titled:
+        assert!(is_transient(&blip(1_000_000, "Ping"), true, &config));
+        // Untitled and stopped quickly, length unknown:
+        assert!(is_transient(&blip(0, ""), true, &config));
+        // One signal alone is not enough:
+        assert!(!is_transient(&blip(1_000_000, "Ping"), false, &config));
+        assert!(!is_transient(&blip(0, ""), false, &config));
+        assert!(!is_transient(&blip(180_000_000, "Song"), true, &config));
+    }
+
+    #[test]
+    fn zero_thresholds_disable_signals() {
+        let config = MediaPlayerConfig {
+            transient_max_length_ms: 0,
+            transient_stop_ms: 0,
+            ..MediaPlayerConfig::default()
+        };
+        assert!(!is_transient(&blip(1_000_000, ""), true, &config));
+
+        let config = MediaPlayerConfig {
+            transient_stop_ms: 0,
+            ..MediaPlayerConfig::default()
+        };
+        assert!(!is_transient(&blip(1_000_000, "Ping"), true, &config));
+        assert!(is_transient(&blip(1_000_000, ""), true, &config));
+    }
+
+    #[test]
+    fn missing_album_still_applies() {
+        let partial = metadata(vec![
+            ("xesam:title", Value::from("Song".to_string())),
+            ("xesam:artist", artists(&["Artist"])),
+        ]);
+        let media_info = applied(&partial, &MediaPlayerConfig::default());
+
+        assert_eq!(media_info.title, "Song");
+        assert_eq!(media_info.artist, ["Artist"]);
+        assert_eq!(media_info.album, "");
+        assert!(media_info.has_metadata);
+    }
+
+    #[test]
+    fn mistyped_keys_read_as_empty() {
+        let odd = metadata(vec![
+            ("xesam:title", Value::from("Song".to_string())),
+            ("xesam:album", Value::I32(7)),
+            ("mpris:length", Value::from("long".to_string())),
+        ]);
+        let media_info = applied(&odd, &MediaPlayerConfig::default());
+
+        assert_eq!(media_info.title, "Song");
+        assert_eq!(media_info.album, "");
+        assert_eq!(media_info.length_raw, 0);
+    }
+
+    #[tokio::test]
+    async fn cancelled_token_ends_wait_on_silent_stream() {
+        let shutdown = CancellationToken::new();
+        let mut stream = futures_util::stream::pending::<()>();
+
+        tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                shutdown.cancel();
+            }
+        });
+        let wake = tokio::time::timeout(
+            Duration::from_secs(1),
+            next_event(&mut stream, &shutdown, None),
+        )
+        .await;
+
+        assert!(matches!(wake, Ok(Wake::Shutdown)));
+    }
+
+    #[tokio::test]
+    async fn wait_wakes_for_message_or_switch() {
+        let shutdown = CancellationToken::new();
+
+        let mut stream = futures_util::stream::iter([7]);
+        let wake = next_event(&mut stream, &shutdown, None).await;
+        assert!(matches!(wake, Wake::Message(Some(7))));
+        let wake = next_event(&mut stream, &shutdown, None).await;
+        assert!(matches!(wake, Wake::Message(None)));
+
+        let mut stream = futures_util::stream::pending::<()>();
+        let deadline = Instant::now() + Duration::from_millis(10);
+        let wake = tokio::time::timeout(
+            Duration::from_secs(1),
+            next_event(&mut stream, &shutdown, Some(deadline)),
+        )
+        .await;
+        assert!(matches!(wake, Ok(Wake::Switch)));
     }
 }
@@ -0,0 +1,99 @@
+// Fill "{name}" placeholders of template. Text between fields is written
+// only between two non-empty ones, so "{artist} — {title}" without artist is
+// just the title. Unknown placeholders are kept as written
+pub(super) fn render(template: &str, field: impl Fn(&str) -> Option<String>) -> String {
+    let mut line = String::new();
+    // Text before the first field, and since the last placeholder:
+    let mut prefix = None;
+    let mut separator = String::new();
+    let mut any_field = false;
+    let mut last_empty = false;
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}').map(|v| start + v) else {
+            break;
+        };
+        separator.push_str(&rest[..start]);
+
+        let name = &rest[start + 1..end];
+        rest = &rest[end + 1..];
+        let Some(value) = field(name) else {
+            separator.push_str(&format!("{{{name}}}"));
+            continue;
+        };
+
+        let separator = std::mem::take(&mut separator);
+        let prefix = prefix.get_or_insert(separator.clone());
+        last_empty = value.is_empty();
+        if last_empty {
+            continue;
+        }
+
+        if any_field {
+            line.push_str(&separator);
+        } else {
+            line.push_str(prefix);
+        }
+        line.push_str(&value);
+        any_field = true;
+    }
+
+    // Trailing text goes with the last field:
+    if any_field && !last_empty {
+        line.push_str(&separator);
+        line.push_str(rest);
+    }
+
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render;
+
+    fn fields(artist: &str, title: &str) -> impl Fn(&str) -> Option<String> {
+        let (artist, title) = (artist.to_string(), title.to_string());
+        move |name| match name {
+            "artist" => Some(artist.clone()),
+            "title" => Some(title.clone()),
+            "album" | "status" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn joins_filled_fields() {
+        assert_eq!(render("{artist} — {title}", fields("A", "T")), "A — T");
+    }
+
+    #[test]
+    fn drops_separator_of_empty_field() {
+        assert_eq!(render("{artist} — {title}", fields("", "T")), "T");
+        assert_eq!(render("{artist} — {title}", fields("A", "")), "A");
+        assert_eq!(
+            render("{artist} — {album} — {title}", fields("A", "T")),
+            "A — T"
+        );
+    }
+
+    #[test]
+    fn all_fields_empty() {
+        assert_eq!(render("{artist} — {title}", fields("", "")), "");
+    }
+
+    #[test]
+    fn keeps_surrounding_text() {
+        assert_eq!(render("♪ {title} ♪", fields("", "T")), "♪ T ♪");
+    }
+
+    #[test]
+    fn keeps_unknown_placeholder() {
+        assert_eq!(render("{title} {foo}", fields("", "T")), "T {foo}");
+    }
+
+    #[test]
+    fn unclosed_brace_ends_template() {
+        assert_eq!(render("{title} {artist", fields("A", "T")), "T {artist");
+    }
+}
@@ -0,0 +1,12 @@
+use super::ModuleInfo;
+use tauri::{AppHandle, Wry};
+
+#[tauri::command]
+pub async fn list_modules(app_handle: AppHandle<Wry>) -> Result<Vec<ModuleInfo>, String> {
+    Ok(super::list_modules(&app_handle).await)
+}
+
+#[tauri::command]
+pub async fn restart_module(app_handle: AppHandle<Wry>, name: String) -> Result<(), String> {
+    super::restart_module(&app_handle, &name).await
+}
@@ -0,0 +1,146 @@
+pub mod commands;
+
+use crate::Battery::{self, BatteryConfig, TauriBattery};
+use crate::DBusBridge::{self, TauriDBusBridge};
+use crate::FocusedWindow::{self, TauriFocusedWindow};
+use crate::MediaPlayer::{self, MediaPlayerConfig, TauriMediaPlayer};
+use crate::SystemTray::{self, TouriSystemTray, TrayBackend};
+use serde::Serialize;
+use std::sync::Arc;
+use tauri::{AppHandle, Manager, Wry};
+
+// Module state for a settings UI, module is enabled when it is managed
+#[derive(Clone, Serialize, Debug)]
+pub struct ModuleInfo {
+    name: &'static str,
+    enabled: bool,
+    // False after its reconnect policy gave up, see restart_module:
+    available: bool,
+    // Event the module sends its updates with:
+    emit_event: &'static str,
+    debounce_ms: Option<u64>,
+    config: ModuleConfig,
+}
+
+#[derive(Clone, Serialize, Debug)]
+#[serde(untagged)]
+pub enum ModuleConfig {
+    MediaPlayer(MediaPlayerConfig),
+    SystemTray(TrayBackend),
+    DBusBridge { max_messages_per_second: u32 },
+    Battery(BatteryConfig),
+    None,
+}
+
+pub async fn list_modules(app_handle: &AppHandle<Wry>) -> Vec<ModuleInfo> {
+    let (media_player, media_available) = match app_handle.try_state::<Arc<TauriMediaPlayer>>() {
+        Some(media_player) => (
+            Some(media_player.config().await),
+            media_player.available().await,
+        ),
+        None => (None, false),
+    };
+    let (system_tray, tray_available) = match app_handle.try_state::<Arc<TouriSystemTray>>() {
+        Some(system_tray) => (
+            Some(system_tray.backend().await),
+            system_tray.available().await,
+        ),
+        None => (None, false),
+    };
+    let (dbus_bridge, bridge_available) = match app_handle.try_state::<Arc<TauriDBusBridge>>() {
+        Some(dbus_bridge) => (true, dbus_bridge.available().await),
+        None => (false, false),
+    };
+    let (battery, battery_available) = match app_handle.try_state::<Arc<TauriBattery>>() {
+        Some(battery) => (Some(battery.config().await), battery.available().await),
+        None => (None, false),
+    };
+    let focused_window = app_handle.try_state::<Arc<TauriFocusedWindow>>();
+
+    vec![
+        ModuleInfo {
+            name: "media_player",
+            enabled: media_player.is_some(),
+            available: media_available,
+            emit_event: MediaPlayer::EMIT_EVENT,
+            debounce_ms: Some(
+                media_player
+                    .as_ref()
+                    .map_or(MediaPlayer::DEBOUNCE_MS, |v| v.metadata_debounce_ms),
+            ),
+            config: match media_player {
+                Some(config) => ModuleConfig::MediaPlayer(config),
+                None => ModuleConfig::None,
+            },
+        },
+        ModuleInfo {
+            name: "system_tray",
+            enabled: system_tray.is_some(),
+            available: tray_available,
+            emit_event: SystemTray::EMIT_EVENT,
+            debounce_ms: None,
+            config: match system_tray {
+                Some(backend) => ModuleConfig::SystemTray(backend),
+                None => ModuleConfig::None,
+            },
+        },
+        ModuleInfo {
+            name: "dbus_bridge",
+            enabled: dbus_bridge,
+            available: bridge_available,
+            emit_event: DBusBridge::EMIT_EVENT,
+            debounce_ms: None,
+            config: if dbus_bridge {
+                ModuleConfig::DBusBridge {
+                    max_messages_per_second: DBusBridge::MAX_MESSAGES_PER_SECOND,
+                }
+            } else {
+                ModuleConfig::None
+            },
+        },
+        ModuleInfo {
+            name: "battery",
+            enabled: battery.is_some(),
+            available: battery_available,
+            emit_event: Battery::EMIT_EVENT,
+            debounce_ms: None,
+            config: match battery {
+                Some(config) => ModuleConfig::Battery(config),
+                None => ModuleConfig::None,
+            },
+        },
+        ModuleInfo {
+            name: "focused_window",
+            enabled: focused_window.is_some(),
+            available: focused_window.is_some(),
+            emit_event: FocusedWindow::EMIT_EVENT,
+            debounce_ms: None,
+            config: ModuleConfig::None,
+        },
+    ]
+}
+
+// Modules that reconnect can give up, see ReconnectPolicy
+pub async fn restart_module(app_handle: &AppHandle<Wry>, name: &str) -> Result<(), String> {
+    match name {
+        "media_player" => match app_handle.try_state::<Arc<TauriMediaPlayer>>() {
+            Some(media_player) => media_player.restart().await,
+            None => Err("Media player is not started".to_string()),
+        },
+        "system_tray" => match app_handle.try_state::<Arc<TouriSystemTray>>() {
+            Some(system_tray) => system_tray.restart().await,
+            None => Err("System tray is not started".to_string()),
+        },
+        "dbus_bridge" => match app_handle.try_state::<Arc<TauriDBusBridge>>() {
+            Some(dbus_bridge) => dbus_bridge.restart().await,
+            None => Err("D-Bus bridge is not started".to_string()),
+        },
+        "battery" => match app_handle.try_state::<Arc<TauriBattery>>() {
+            Some(battery) => battery.restart().await,
+            None => Err("Battery is not started".to_string()),
+        },
+        // X11 only, there is no bus to lose:
+        "focused_window" => Err(format!("Module {name} doesn't reconnect")),
+        _ => Err(format!("Unknown module {name}")),
+    }
+}
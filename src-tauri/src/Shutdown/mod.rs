@@ -0,0 +1,133 @@
+use crate::Log;
+use futures_util::future::join_all;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tauri::async_runtime;
+use tokio_util::sync::CancellationToken;
+
+// Max time to wait for module threads on exit:
+const EXIT_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Exit signal shared by modules, every task selects on the token
+pub struct Shutdown {
+    token: CancellationToken,
+    threads: Mutex<Vec<JoinHandle<()>>>,
+    tasks: Mutex<Vec<async_runtime::JoinHandle<()>>>,
+}
+
+impl Shutdown {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            token: CancellationToken::new(),
+            threads: Mutex::new(Vec::new()),
+            tasks: Mutex::new(Vec::new()),
+        })
+    }
+
+    pub fn token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+
+    // Run module task on the runtime shared with Tauri, so exit waits for it
+    pub fn spawn<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let task = async_runtime::spawn(task);
+        self.tasks.lock().unwrap().push(task);
+    }
+
+    // Remember module thread, so exit waits for it. For blocking work only,
+    // async work goes through spawn
+    pub fn track(&self, thread: JoinHandle<()>) {
+        self.threads.lock().unwrap().push(thread);
+    }
+
+    // Cancel all tasks and wait until tasks and threads are finished
+    pub fn trigger(&self) {
+        self.token.cancel();
+
+        let deadline = Instant::now() + EXIT_TIMEOUT;
+        let tasks: Vec<async_runtime::JoinHandle<()>> =
+            self.tasks.lock().unwrap().drain(..).collect();
+        let count = tasks.len();
+        let finished = async_runtime::block_on(async move {
+            tokio::time::timeout(EXIT_TIMEOUT, join_all(tasks)).await
+        });
+        if finished.is_err() {
+            Log::warn(
+                "shutdown",
+                format!("Shutdown: some of {count} module tasks are still running after {EXIT_TIMEOUT:?}"),
+            );
+        }
+
+        let threads: Vec<JoinHandle<()>> = self.threads.lock().unwrap().drain(..).collect();
+
+        for thread in threads {
+            while !thread.is_finished() && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(10));
+            }
+
+            if thread.is_finished() {
+                let _ = thread.join();
+            } else {
+                Log::warn(
+                    "shutdown",
+                    format!("Shutdown: module thread is still running after {EXIT_TIMEOUT:?}"),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Shutdown;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn trigger_stops_tasks_selecting_on_token() {
+        let shutdown = Shutdown::new();
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let token = shutdown.token();
+        let task_stopped = stopped.clone();
+        shutdown.spawn(async move {
+            // Stands in for a listen loop waiting on its stream:
+            tokio::select! {
+                _ = token.cancelled() => {}
+                _ = tokio::time::sleep(Duration::from_secs(60)) => return,
+            }
+            task_stopped.store(true, Ordering::SeqCst);
+        });
+
+        let started = Instant::now();
+        shutdown.trigger();
+
+        assert!(stopped.load(Ordering::SeqCst));
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn trigger_waits_for_tracked_threads() {
+        let shutdown = Shutdown::new();
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let token = shutdown.token();
+        let thread_stopped = stopped.clone();
+        shutdown.track(thread::spawn(move || {
+            while !token.is_cancelled() {
+                thread::sleep(Duration::from_millis(1));
+            }
+            thread_stopped.store(true, Ordering::SeqCst);
+        }));
+        shutdown.trigger();
+
+        assert!(stopped.load(Ordering::SeqCst));
+    }
+}
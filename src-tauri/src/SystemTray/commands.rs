@@ -0,0 +1,194 @@
+use super::{
+    TouriSystemTray, TrayDebugState, TrayItem, TrayMenu, TrayPropertyError, TrayTooltip, EMIT_EVENT,
+};
+use crate::Emit;
+use serde_json::Value as JsonValue;
+use std::sync::Arc;
+use tauri::{AppHandle, State, Wry};
+
+#[tauri::command]
+pub async fn tray_debug_state(
+    system_tray: State<'_, Arc<TouriSystemTray>>,
+) -> Result<TrayDebugState, String> {
+    Ok(system_tray.debug_state().await)
+}
+
+#[tauri::command]
+pub async fn tray_get_items(
+    system_tray: State<'_, Arc<TouriSystemTray>>,
+) -> Result<Vec<TrayItem>, String> {
+    system_tray.items().await
+}
+
+// Full snapshot for frontend reloads and theme changes
+#[tauri::command]
+pub async fn tray_emit_full(
+    system_tray: State<'_, Arc<TouriSystemTray>>,
+) -> Result<Vec<TrayItem>, String> {
+    system_tray.emit_full().await
+}
+
+// Re-resolves all icons and sends the list, keeping the theme cache
+#[tauri::command]
+pub async fn tray_prewarm(
+    system_tray: State<'_, Arc<TouriSystemTray>>,
+) -> Result<Vec<TrayItem>, String> {
+    system_tray.prewarm().await
+}
+
+#[tauri::command]
+pub async fn tray_get_tooltip(
+    service: String,
+    system_tray: State<'_, Arc<TouriSystemTray>>,
+) -> Result<TrayTooltip, String> {
+    system_tray.tooltip(&service).await
+}
+
+#[tauri::command]
+pub async fn tray_set_tooltip_fallback(
+    enabled: bool,
+    system_tray: State<'_, Arc<TouriSystemTray>>,
+) -> Result<(), String> {
+    system_tray.set_tooltip_app_fallback(enabled).await;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn tray_set_hidden(
+    app_id: String,
+    hidden: bool,
+    system_tray: State<'_, Arc<TouriSystemTray>>,
+) -> Result<(), String> {
+    system_tray.set_hidden(&app_id, hidden).await
+}
+
+#[tauri::command]
+pub async fn tray_set_fallback_icon(
+    name: Option<String>,
+    system_tray: State<'_, Arc<TouriSystemTray>>,
+) -> Result<(), String> {
+    system_tray.set_fallback_icon(name).await;
+    Ok(())
+}
+
+// Raw StatusNotifierItem property, for debugging how an item renders
+#[tauri::command]
+pub async fn tray_get_property(
+    service: String,
+    property: String,
+    system_tray: State<'_, Arc<TouriSystemTray>>,
+) -> Result<JsonValue, TrayPropertyError> {
+    system_tray.property_json(&service, &property).await
+}
+
+// Returns the menu when the item has only a menu, None when it was activated
+#[tauri::command]
+pub async fn tray_activate(
+    service: String,
+    x: i32,
+    y: i32,
+    system_tray: State<'_, Arc<TouriSystemTray>>,
+) -> Result<Option<TrayMenu>, String> {
+    system_tray
+        .inner()
+        .clone()
+        .activate(service, x, y, false)
+        .await
+}
+
+#[tauri::command]
+pub async fn tray_secondary_activate(
+    service: String,
+    x: i32,
+    y: i32,
+    system_tray: State<'_, Arc<TouriSystemTray>>,
+) -> Result<Option<TrayMenu>, String> {
+    system_tray
+        .inner()
+        .clone()
+        .activate(service, x, y, true)
+        .await
+}
+
+#[tauri::command]
+pub async fn tray_scroll(
+    service: String,
+    delta: i32,
+    orientation: String,
+    system_tray: State<'_, Arc<TouriSystemTray>>,
+) -> Result<(), String> {
+    system_tray.scroll(&service, delta, &orientation).await
+}
+
+#[tauri::command]
+pub async fn tray_menu_open(
+    service: String,
+    system_tray: State<'_, Arc<TouriSystemTray>>,
+) -> Result<TrayMenu, String> {
+    system_tray.inner().clone().open_menu(service).await
+}
+
+#[tauri::command]
+pub async fn tray_menu_close(
+    service: String,
+    system_tray: State<'_, Arc<TouriSystemTray>>,
+) -> Result<(), String> {
+    system_tray.close_menu(&service);
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn tray_menu_activate(
+    service: String,
+    id: i32,
+    system_tray: State<'_, Arc<TouriSystemTray>>,
+) -> Result<TrayMenu, String> {
+    system_tray.activate_menu_item(&service, id).await
+}
+
+// Same as tray_menu_activate, by the name the menu frontend uses
+#[tauri::command]
+pub async fn tray_menu_click(
+    service: String,
+    item_id: i32,
+    system_tray: State<'_, Arc<TouriSystemTray>>,
+) -> Result<TrayMenu, String> {
+    system_tray.activate_menu_item(&service, item_id).await
+}
+
+#[tauri::command]
+pub async fn tray_menu_refresh(
+    service: String,
+    system_tray: State<'_, Arc<TouriSystemTray>>,
+) -> Result<TrayMenu, String> {
+    system_tray.refresh_menu(&service).await
+}
+
+// Emit only these services with onTrayItemsUpdate
+#[tauri::command]
+pub async fn tray_subscribe(
+    services: Vec<String>,
+    system_tray: State<'_, Arc<TouriSystemTray>>,
+) -> Result<(), String> {
+    system_tray.subscribe(services).await;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn tray_subscribe_all(
+    system_tray: State<'_, Arc<TouriSystemTray>>,
+) -> Result<(), String> {
+    system_tray.subscribe_all().await;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn tray_clear_items(
+    app_handle: AppHandle<Wry>,
+    system_tray: State<'_, Arc<TouriSystemTray>>,
+) -> Result<(), String> {
+    system_tray.clear_items().await?;
+    let _ = Emit::emit(&app_handle, "system_tray", EMIT_EVENT, Vec::<String>::new());
+
+    Ok(())
+}
@@ -1,68 +1,463 @@
-use futures_util::StreamExt;
-use std::thread;
-use std::{collections::HashSet, sync::Arc};
+use super::item::{fetch_item, ITEM_EVENT};
+use super::{
+    is_subscribed, item_address, Subscription, FREEDESKTOP_ITEM_INTERFACE, ITEM_INTERFACE,
+    ITEM_PATH, KDE_WATCHER_NAME, WATCHER_NAME, WATCHER_PATH,
+};
+use crate::Bus::{keep_connected, Attempt, Availability, BusConfig, ReconnectPolicy};
+use crate::Emit;
+use crate::Log;
+use crate::Shutdown::Shutdown;
+use futures_util::{stream, StreamExt};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, Wry};
 use tokio::sync::RwLock;
-use zbus::fdo::DBusProxy;
-use zbus::{dbus_interface, ConnectionBuilder, SignalContext};
-use zbus::{Connection, Result};
+use tokio::task::JoinHandle;
+use zbus::fdo::{DBusProxy, NameLost, PropertiesProxy, RequestNameFlags, RequestNameReply};
+use zbus::names::InterfaceName;
+use zbus::{dbus_interface, MessageHeader, SignalContext};
+use zbus::{Connection, Proxy, Result, SignalStream};
+
+// Failed signals in a row before the watcher is served again:
+const MAX_SIGNAL_FAILURES: u32 = 5;
+// Registered services, sent with every change of them:
+const ITEMS_EVENT: &str = "onTrayItemsChanged";
+// Item signals after which the item is read again:
+const ITEM_SIGNALS: [&str; 5] = [
+    "NewIcon",
+    "NewAttentionIcon",
+    "NewStatus",
+    "NewTitle",
+    "NewToolTip",
+];
+
+// Failed signal rounds in a row, a success starts over
+#[derive(Default)]
+struct SignalFailures(u32);
+
+impl SignalFailures {
+    // True when the watcher must be served again, counting starts over then
+    fn record(&mut self, result: &Result<()>) -> bool {
+        if result.is_ok() {
+            self.0 = 0;
+            return false;
+        }
+
+        self.0 += 1;
+        if self.0 < MAX_SIGNAL_FAILURES {
+            return false;
+        }
+        self.0 = 0;
+        true
+    }
+}
 
 #[derive(Clone)]
 struct Watcher {
+    // None in tests, events then go nowhere:
+    app_handle: Option<AppHandle<Wry>>,
     conn: Arc<Connection>,
     path: String,
     items: Arc<RwLock<HashSet<String>>>,
     hosts: Arc<RwLock<HashSet<String>>>,
+    subscription: Subscription,
+    // Signal listener of every registered item:
+    item_watchers: Arc<Mutex<HashMap<String, JoinHandle<()>>>>,
 }
 
 impl Watcher {
-    async fn new(conn: Arc<Connection>, path: impl Into<String>) -> Self {
+    async fn new(
+        app_handle: Option<AppHandle<Wry>>,
+        conn: Arc<Connection>,
+        path: impl Into<String>,
+        subscription: Subscription,
+    ) -> Self {
         Self {
+            app_handle,
             conn,
             path: path.into(),
             items: Arc::new(RwLock::new(HashSet::new())),
             hosts: Arc::new(RwLock::new(HashSet::new())),
+            subscription,
+            item_watchers: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
     fn make_signal(&self) -> zbus::Result<SignalContext<'_>> {
         SignalContext::new(&*self.conn, self.path.as_str())
     }
-}
 
-#[dbus_interface(name = "org.freedesktop.StatusNotifierWatcher")]
-impl Watcher {
-    async fn RegisterStatusNotifierItem(&self, service: &str) -> zbus::fdo::Result<()> {
-        let mut items = self.items.write().await;
-        if items.insert(service.to_string()) {
-            let ctx = self
-                .make_signal()
-                .map_err(|e| zbus::fdo::Error::Failed(e.to_string()))?;
-            let _ = Self::StatusNotifierItemRegistered(&ctx, service).await;
+    async fn snapshot(&self) -> (Vec<String>, Vec<String>) {
+        let mut items: Vec<String> = self.items.read().await.iter().cloned().collect();
+        let mut hosts: Vec<String> = self.hosts.read().await.iter().cloned().collect();
+        items.sort();
+        hosts.sort();
+
+        (items, hosts)
+    }
+
+    async fn emit_items(&self) {
+        let (mut items, _) = self.snapshot().await;
+        let subscription = self.subscription.read().await;
+        items.retain(|service| is_subscribed(&subscription, service));
+        drop(subscription);
+        if let Some(app_handle) = &self.app_handle {
+            let _ = Emit::emit(app_handle, "system_tray", ITEMS_EVENT, items);
         }
+    }
+
+    async fn register_item(&self, service: String) -> zbus::Result<()> {
+        if !claim(&self.items, &service).await {
+            return Ok(());
+        }
+
+        self.watch_item(service.clone()).await;
+        let _ = self.signal_item(&service, true).await;
+        self.emit_items().await;
         Ok(())
     }
 
-    async fn RegisterStatusNotifierHost(&self, service: &str) -> zbus::fdo::Result<()> {
+    // Service is a bus name or just an object path (libappindicator), the
+    // latter is kept as "sender/path" so the item can be found again
+    async fn register_service(
+        &self,
+        service: &str,
+        header: &MessageHeader<'_>,
+    ) -> zbus::fdo::Result<()> {
+        let service = match service.starts_with('/') {
+            true => match header.sender() {
+                Ok(Some(sender)) => format!("{sender}{service}"),
+                _ => return Err(zbus::fdo::Error::InvalidArgs(service.to_string())),
+            },
+            false => service.to_string(),
+        };
+
+        self.register_item(service)
+            .await
+            .map_err(|e| zbus::fdo::Error::Failed(e.to_string()))
+    }
+
+    async fn register_host(&self, service: &str) -> zbus::fdo::Result<()> {
         let mut hosts = self.hosts.write().await;
+        let first = hosts.is_empty();
         if hosts.insert(service.to_string()) {
-            let ctx = self
-                .make_signal()
-                .map_err(|e| zbus::fdo::Error::Failed(e.to_string()))?;
-            let _ = Self::StatusNotifierHostRegistered(&ctx).await;
+            drop(hosts);
+            let _ = self.signal_host(true, first).await;
+        }
+        Ok(())
+    }
+
+    async fn item_list(&self) -> Vec<String> {
+        self.items.read().await.iter().cloned().collect()
+    }
+
+    async fn has_hosts(&self) -> bool {
+        !self.hosts.read().await.is_empty()
+    }
+
+    // Clients listen on either interface name, signals go out on both.
+    // All are sent even if one fails, failures are logged here
+    async fn signal_item(&self, service: &str, registered: bool) -> zbus::Result<()> {
+        let ctx = match self.make_signal() {
+            Ok(ctx) => ctx,
+            Err(err) => return self.first_error([Err(err)]),
+        };
+        let kde_watcher = KdeWatcher(self.clone());
+        let mut results = Vec::new();
+        if registered {
+            results.push(Self::StatusNotifierItemRegistered(&ctx, service).await);
+            results.push(KdeWatcher::StatusNotifierItemRegistered(&ctx, service).await);
+        } else {
+            results.push(Self::StatusNotifierItemUnregistered(&ctx, service).await);
+            results.push(KdeWatcher::StatusNotifierItemUnregistered(&ctx, service).await);
+        }
+        results.push(self.registered_status_notifier_items_changed(&ctx).await);
+        results.push(
+            kde_watcher
+                .registered_status_notifier_items_changed(&ctx)
+                .await,
+        );
+
+        self.first_error(results)
+    }
+
+    // Flipped: IsStatusNotifierHostRegistered changed with this host
+    async fn signal_host(&self, registered: bool, flipped: bool) -> zbus::Result<()> {
+        let ctx = match self.make_signal() {
+            Ok(ctx) => ctx,
+            Err(err) => return self.first_error([Err(err)]),
+        };
+        let mut results = Vec::new();
+        if registered {
+            results.push(Self::StatusNotifierHostRegistered(&ctx).await);
+            results.push(KdeWatcher::StatusNotifierHostRegistered(&ctx).await);
+        } else {
+            results.push(Self::StatusNotifierHostUnregistered(&ctx).await);
+            results.push(KdeWatcher::StatusNotifierHostUnregistered(&ctx).await);
+        }
+        if flipped {
+            results.push(self.is_status_notifier_host_registered_changed(&ctx).await);
+            results.push(
+                KdeWatcher(self.clone())
+                    .is_status_notifier_host_registered_changed(&ctx)
+                    .await,
+            );
+        }
+
+        self.first_error(results)
+    }
+
+    fn first_error(&self, results: impl IntoIterator<Item = Result<()>>) -> Result<()> {
+        let mut first = Ok(());
+        for result in results {
+            if let Err(err) = result {
+                Log::error(
+                    "system_tray",
+                    format!("SystemTrayEmulator signal err: {err}"),
+                );
+                if first.is_ok() {
+                    first = Err(err);
+                }
+            }
+        }
+        first
+    }
+
+    // Follow item changes, apps switch icons (muted, new message) this way.
+    // Items use either interface name for their signals, both are followed
+    async fn watch_item(&self, service: String) {
+        let (name, path) = item_address(&service);
+        let mut streams = Vec::new();
+        for interface in [ITEM_INTERFACE, FREEDESKTOP_ITEM_INTERFACE] {
+            match item_signals(&self.conn, name, path, interface).await {
+                Ok(signals) => streams.push(signals),
+                Err(err) => {
+                    Log::error(
+                        "system_tray",
+                        format!("SystemTrayEmulator item err: {service}: {err}"),
+                    );
+                    return;
+                }
+            }
+        }
+
+        let app_handle = self.app_handle.clone();
+        let connection = self.conn.clone();
+        let subscription = self.subscription.clone();
+        let watched_service = service.clone();
+        let task = tokio::spawn(async move {
+            let mut signals = stream::select_all(streams);
+            while let Some(message) = signals.next().await {
+                let member = message.member();
+                if !member.is_some_and(|v| ITEM_SIGNALS.contains(&v.as_str())) {
+                    continue;
+                }
+                if !is_subscribed(&*subscription.read().await, &watched_service) {
+                    continue;
+                }
+
+                match fetch_item(&connection, &watched_service).await {
+                    Ok(item) => {
+                        if let Some(app_handle) = &app_handle {
+                            let _ = Emit::emit(app_handle, "system_tray", ITEM_EVENT, item);
+                        }
+                    }
+                    // Item is going away, NameOwnerChanged cleans it up:
+                    Err(err) => Log::error(
+                        "system_tray",
+                        format!("SystemTrayEmulator item err: {watched_service}: {err}"),
+                    ),
+                }
+            }
+        });
+
+        if let Some(previous) = self.item_watchers.lock().unwrap().insert(service, task) {
+            previous.abort();
+        }
+    }
+
+    fn unwatch_items(&self, services: &[String]) {
+        let mut item_watchers = self.item_watchers.lock().unwrap();
+        for service in services {
+            if let Some(task) = item_watchers.remove(service) {
+                task.abort();
+            }
+        }
+    }
+
+    // Host left the bus: apps waiting for a host see it through the
+    // signal and IsStatusNotifierHostRegistered
+    async fn remove_host(&self, service: &str) -> zbus::Result<()> {
+        let mut hosts = self.hosts.write().await;
+        if !hosts.remove(service) {
+            return Ok(());
+        }
+        let last = hosts.is_empty();
+        drop(hosts);
+
+        self.signal_host(false, last).await
+    }
+
+    // Name left the bus: its host and items go. Signals failing round after
+    // round get the watcher served again, Err when even that failed
+    async fn owner_gone(&self, name: &str, failures: &mut SignalFailures) -> Result<()> {
+        // Failed signals are logged by the watcher:
+        let _ = self.remove_host(name).await;
+
+        // Items of the name, also ones registered as "name/path":
+        let removed: Vec<String> = {
+            let mut items = self.items.write().await;
+            let removed: Vec<String> = items
+                .iter()
+                .filter(|v| item_address(v).0 == name)
+                .cloned()
+                .collect();
+            for service in &removed {
+                items.remove(service);
+            }
+            removed
+        };
+        if removed.is_empty() {
+            return Ok(());
+        }
+        self.unwatch_items(&removed);
+
+        // One failed signal must not stop tracking of removed items:
+        let mut emitted = Ok(());
+        for service in &removed {
+            emitted = emitted.and(self.signal_item(service, false).await);
+        }
+        self.emit_items().await;
+
+        if !failures.record(&emitted) {
+            return Ok(());
+        }
+        Log::warn(
+            "system_tray",
+            format!("SystemTrayEmulator: {MAX_SIGNAL_FAILURES} failed signals, restarting watcher"),
+        );
+        SystemTrayEmulator::unserve_watcher(&self.conn).await?;
+        SystemTrayEmulator::serve_watcher(&self.conn, self).await
+    }
+
+    async fn clear_items(&self) -> zbus::Result<Vec<String>> {
+        let removed: Vec<String> = self.items.write().await.drain().collect();
+        self.unwatch_items(&removed);
+
+        for service in &removed {
+            let _ = self.signal_item(service, false).await;
         }
+        self.emit_items().await;
+
+        Ok(removed)
+    }
+}
+
+// Insert decides under the lock, so of racing registrations only one
+// signals and watches the item
+async fn claim(items: &RwLock<HashSet<String>>, service: &str) -> bool {
+    items.write().await.insert(service.to_string())
+}
+
+async fn item_signals(
+    connection: &Connection,
+    name: &str,
+    path: &str,
+    interface: &'static str,
+) -> Result<SignalStream<'static>> {
+    let proxy = Proxy::new(connection, name.to_string(), path.to_string(), interface).await?;
+    proxy.receive_all_signals().await
+}
+
+#[dbus_interface(name = "org.freedesktop.StatusNotifierWatcher")]
+impl Watcher {
+    async fn RegisterStatusNotifierItem(
+        &self,
+        service: &str,
+        #[zbus(header)] header: MessageHeader<'_>,
+    ) -> zbus::fdo::Result<()> {
+        self.register_service(service, &header).await
+    }
+
+    async fn RegisterStatusNotifierHost(&self, service: &str) -> zbus::fdo::Result<()> {
+        self.register_host(service).await
+    }
+
+    #[dbus_interface(property)]
+    async fn RegisteredStatusNotifierItems(&self) -> Vec<String> {
+        self.item_list().await
+    }
+
+    #[dbus_interface(property)]
+    async fn IsStatusNotifierHostRegistered(&self) -> bool {
+        self.has_hosts().await
+    }
+
+    // Strict items check it, the spec only defines version 0
+    #[dbus_interface(property)]
+    async fn ProtocolVersion(&self) -> i32 {
+        0
+    }
+
+    #[dbus_interface(signal)]
+    async fn StatusNotifierItemRegistered(
+        ctx: &SignalContext<'_>,
+        _service: &str,
+    ) -> zbus::Result<()> {
+        Ok(())
+    }
+
+    #[dbus_interface(signal)]
+    async fn StatusNotifierItemUnregistered(
+        ctx: &SignalContext<'_>,
+        _service: &str,
+    ) -> zbus::Result<()> {
+        Ok(())
+    }
+
+    #[dbus_interface(signal)]
+    async fn StatusNotifierHostRegistered(ctx: &SignalContext<'_>) -> zbus::Result<()> {
+        Ok(())
+    }
+
+    #[dbus_interface(signal)]
+    async fn StatusNotifierHostUnregistered(ctx: &SignalContext<'_>) -> zbus::Result<()> {
         Ok(())
     }
+}
+
+// Same watcher under the KDE interface name, Qt and libappindicator items
+// register through it
+#[derive(Clone)]
+struct KdeWatcher(Watcher);
+
+#[dbus_interface(name = "org.kde.StatusNotifierWatcher")]
+impl KdeWatcher {
+    async fn RegisterStatusNotifierItem(
+        &self,
+        service: &str,
+        #[zbus(header)] header: MessageHeader<'_>,
+    ) -> zbus::fdo::Result<()> {
+        self.0.register_service(service, &header).await
+    }
+
+    async fn RegisterStatusNotifierHost(&self, service: &str) -> zbus::fdo::Result<()> {
+        self.0.register_host(service).await
+    }
 
     #[dbus_interface(property)]
     async fn RegisteredStatusNotifierItems(&self) -> Vec<String> {
-        let items = self.items.read().await;
-        items.iter().cloned().collect()
+        self.0.item_list().await
     }
 
     #[dbus_interface(property)]
     async fn IsStatusNotifierHostRegistered(&self) -> bool {
-        let hosts = self.hosts.read().await;
-        !hosts.is_empty()
+        self.0.has_hosts().await
+    }
+
+    #[dbus_interface(property)]
+    async fn ProtocolVersion(&self) -> i32 {
+        0
     }
 
     #[dbus_interface(signal)]
@@ -85,71 +480,518 @@ impl Watcher {
     async fn StatusNotifierHostRegistered(ctx: &SignalContext<'_>) -> zbus::Result<()> {
         Ok(())
     }
+
+    #[dbus_interface(signal)]
+    async fn StatusNotifierHostUnregistered(ctx: &SignalContext<'_>) -> zbus::Result<()> {
+        Ok(())
+    }
 }
 
-pub struct SystemTrayEmulator {}
+pub struct SystemTrayEmulator {
+    app_handle: AppHandle<Wry>,
+    watcher: RwLock<Option<Watcher>>,
+    // Unique name of our connection, to tell our watcher names from a real one:
+    unique_name: RwLock<Option<String>>,
+    bus: BusConfig,
+    subscription: Subscription,
+    reconnect: ReconnectPolicy,
+    availability: Availability,
+    shutdown: Arc<Shutdown>,
+}
 
 impl SystemTrayEmulator {
-    pub fn new() -> Arc<Self> {
+    pub fn new(
+        app_handle: AppHandle<Wry>,
+        shutdown: Arc<Shutdown>,
+        bus: BusConfig,
+        subscription: Subscription,
+        reconnect: ReconnectPolicy,
+    ) -> Arc<Self> {
         // Create new emulator for StatusNotifier
-        let instance = Arc::new(Self {});
+        let instance = Arc::new(Self {
+            app_handle,
+            watcher: RwLock::new(None),
+            unique_name: RwLock::new(None),
+            bus,
+            subscription,
+            reconnect,
+            availability: Availability::default(),
+            shutdown,
+        });
 
+        // Served again when the bus drops our connection, until stepping down:
         let cloned_instance = instance.clone();
-        thread::spawn(|| {
-            let runtime = tokio::runtime::Runtime::new().unwrap();
-            runtime.block_on(async move {
-                if let Err(err) = cloned_instance.start().await {
-                    eprintln!("SystemTrayEmulator err: {}", err);
-                }
-            });
+        instance.shutdown.spawn(async move {
+            let shutdown = cloned_instance.shutdown.token();
+            keep_connected(
+                "system_tray",
+                cloned_instance.reconnect.clone(),
+                &cloned_instance.availability,
+                &shutdown,
+                || cloned_instance.session(),
+            )
+            .await;
         });
 
         instance
     }
 
-    async fn start(&self) -> Result<()> {
-        // Create service:
-        let connection = ConnectionBuilder::session()?
-            .name("org.freedesktop.StatusNotifierWatcher")?
-            .name("org.kde.StatusNotifierWatcher")?
-            .build()
+    pub async fn available(&self) -> bool {
+        self.availability.available().await
+    }
+
+    pub async fn restart(&self) -> std::result::Result<(), String> {
+        self.availability.restart().await
+    }
+
+    async fn session(&self) -> Attempt {
+        match self.start().await {
+            Ok(Attempt::Dropped) => {
+                Log::warn(
+                    "system_tray",
+                    "SystemTrayEmulator: bus connection lost, serving again",
+                );
+                Attempt::Dropped
+            }
+            Ok(attempt) => attempt,
+            Err(err) => {
+                Log::error("system_tray", format!("SystemTrayEmulator err: {err}"));
+                Attempt::Failed
+            }
+        }
+    }
+
+    // Registered items and hosts, empty until the watcher is served
+    pub async fn registry(&self) -> (Vec<String>, Vec<String>) {
+        match self.watcher.read().await.as_ref() {
+            Some(watcher) => watcher.snapshot().await,
+            None => (Vec::new(), Vec::new()),
+        }
+    }
+
+    pub async fn unique_name(&self) -> Option<String> {
+        self.unique_name.read().await.clone()
+    }
+
+    // Drop every registered item, apps register again on their next update
+    pub async fn clear_items(&self) -> Result<Vec<String>> {
+        match self.watcher.read().await.as_ref() {
+            Some(watcher) => watcher.clear_items().await,
+            None => Ok(Vec::new()),
+        }
+    }
+
+    async fn serve_watcher(connection: &Connection, watcher: &Watcher) -> Result<()> {
+        let object_server = connection.object_server();
+        if !object_server.at(WATCHER_PATH, watcher.clone()).await? {
+            // Stale watcher left at the path, replace it once:
+            Log::warn(
+                "system_tray",
+                format!("SystemTrayEmulator: {WATCHER_PATH} is already served, replacing it"),
+            );
+            Self::unserve_watcher(connection).await?;
+
+            if !object_server.at(WATCHER_PATH, watcher.clone()).await? {
+                return Err(zbus::Error::Failure(format!(
+                    "Can't serve watcher at {WATCHER_PATH}"
+                )));
+            }
+        }
+
+        // Left from before unless the replace above removed it:
+        let _ = object_server.remove::<KdeWatcher, _>(WATCHER_PATH).await;
+        object_server
+            .at(WATCHER_PATH, KdeWatcher(watcher.clone()))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn unserve_watcher(connection: &Connection) -> Result<()> {
+        let object_server = connection.object_server();
+        object_server.remove::<Watcher, _>(WATCHER_PATH).await?;
+        // Missing when serving stopped half way:
+        let _ = object_server.remove::<KdeWatcher, _>(WATCHER_PATH).await;
+        Ok(())
+    }
+
+    // Apps register only when a watcher appears, so after a quick restart the
+    // tray stays empty. Items owning a well-known org.kde.StatusNotifierItem-*
+    // name are taken back directly. Items registered by unique name and custom
+    // path (e.g. libayatana) can't be found this way and come back on their own
+    async fn adopt_items(connection: &Connection, dbus_proxy: &DBusProxy<'_>, watcher: &Watcher) {
+        let names = match dbus_proxy.list_names().await {
+            Ok(names) => names,
+            Err(err) => {
+                Log::error(
+                    "system_tray",
+                    format!("SystemTrayEmulator list names err: {err}"),
+                );
+                return;
+            }
+        };
+
+        for name in names {
+            if !name.starts_with("org.kde.StatusNotifierItem-")
+                && !name.starts_with("org.freedesktop.StatusNotifierItem-")
+            {
+                continue;
+            }
+
+            // Only names really serving an item:
+            let is_item = match PropertiesProxy::builder(connection)
+                .destination(name.to_string())
+                .and_then(|v| v.path(ITEM_PATH))
+            {
+                Ok(builder) => match builder.build().await {
+                    Ok(proxy) => proxy
+                        .get(
+                            InterfaceName::from_static_str_unchecked(ITEM_INTERFACE),
+                            "Id",
+                        )
+                        .await
+                        .is_ok(),
+                    Err(_) => false,
+                },
+                Err(_) => false,
+            };
+
+            if is_item {
+                let _ = watcher.register_item(name.to_string()).await;
+            }
+        }
+    }
+
+    // A real watcher replaced us: give it our items, then stop serving.
+    // Items kept as "sender/path" can't be forwarded, the watcher would take
+    // the path as ours. They register again when the new watcher appears
+    async fn step_down(connection: &Connection, watcher: &Watcher, lost: NameLost) {
+        let Ok(args) = lost.args() else {
+            return;
+        };
+        let name = args.name().to_string();
+        let (items, _) = watcher.snapshot().await;
+        let (items, skipped): (Vec<String>, Vec<String>) =
+            items.into_iter().partition(|v| !v.contains('/'));
+        Log::warn(
+            "system_tray",
+            format!(
+                "SystemTrayEmulator: real watcher took over {name}, forwarding {} items",
+                items.len()
+            ),
+        );
+        for service in &skipped {
+            Log::warn(
+                "system_tray",
+                format!("SystemTrayEmulator: not forwarding {service}, it has a custom path"),
+            );
+        }
+
+        for service in items {
+            let result = connection
+                .call_method(
+                    Some(name.as_str()),
+                    WATCHER_PATH,
+                    Some(name.as_str()),
+                    "RegisterStatusNotifierItem",
+                    &(service.as_str()),
+                )
+                .await;
+            if let Err(err) = result {
+                Log::error(
+                    "system_tray",
+                    format!("SystemTrayEmulator forward err: {service}: {err}"),
+                );
+            }
+        }
+    }
+
+    // Dropped when the bus closed our connection, Finished after stepping
+    // down or on shutdown
+    async fn start(&self) -> Result<Attempt> {
+        // Create service. Names allow replacement, so a panel started
+        // later takes over instead of failing on them. Own connection, not
+        // the shared one: the names and NameLost belong to it:
+        let connection = self.bus.builder()?.build().await?;
+        *self.unique_name.write().await = connection.unique_name().map(|v| v.to_string());
+        let mut name_lost = DBusProxy::new(&connection)
+            .await?
+            .receive_name_lost()
             .await?;
+        let flags = RequestNameFlags::AllowReplacement
+            | RequestNameFlags::ReplaceExisting
+            | RequestNameFlags::DoNotQueue;
+        for name in [WATCHER_NAME, KDE_WATCHER_NAME] {
+            let reply = connection.request_name_with_flags(name, flags).await?;
+            if reply != RequestNameReply::PrimaryOwner {
+                return Err(zbus::Error::Failure(format!("{name} is already owned")));
+            }
+        }
 
         let arc_conn = Arc::new(connection);
         // Create watcher:
-        let watcher = Watcher::new(arc_conn.clone(), "/StatusNotifierWatcher").await;
+        let watcher = Watcher::new(
+            Some(self.app_handle.clone()),
+            arc_conn.clone(),
+            WATCHER_PATH,
+            self.subscription.clone(),
+        )
+        .await;
 
         // Link command to watcher:
-        let _ = arc_conn
-            .object_server()
-            .at("/StatusNotifierWatcher", watcher.clone())
-            .await;
+        Self::serve_watcher(&arc_conn, &watcher).await?;
+        *self.watcher.write().await = Some(watcher.clone());
 
         // And create listener for removed items:
         let dbus_proxy = DBusProxy::new(&arc_conn.clone()).await?;
+
+        // Items of apps still running from before a restart:
+        Self::adopt_items(&arc_conn, &dbus_proxy, &watcher).await;
         let mut stream = dbus_proxy.receive_name_owner_changed().await?;
-        let items = watcher.items.clone();
+        let shutdown = self.shutdown.token();
+        let mut signal_failures = SignalFailures::default();
+        // Stream ends when the connection is gone:
+        let mut ended = Attempt::Dropped;
 
-        while let Some(signal) = stream.next().await {
+        while let Some(signal) = tokio::select! {
+            signal = stream.next() => signal,
+            Some(lost) = name_lost.next() => {
+                Self::step_down(&arc_conn, &watcher, lost).await;
+                ended = Attempt::Finished;
+                None
+            }
+            _ = shutdown.cancelled() => {
+                ended = Attempt::Finished;
+                None
+            }
+        } {
             if let Ok(args) = signal.args() {
-                let name = args.name().clone();
-
                 let old_owner_present = args.old_owner().as_ref().is_some();
                 let new_owner_present = args.new_owner().as_ref().is_some();
 
                 if old_owner_present && !new_owner_present {
-                    let mut items = items.write().await;
-                    let signal = watcher
-                        .make_signal()
-                        .map_err(|e| zbus::fdo::Error::Failed(e.to_string()))?;
-
-                    if items.remove(&name.to_string()) {
-                        let _ = Watcher::StatusNotifierItemUnregistered(&signal, &name).await;
+                    // Without a served watcher there is nothing to track, clean up:
+                    let name = args.name().as_str();
+                    if let Err(err) = watcher.owner_gone(name, &mut signal_failures).await {
+                        Log::error(
+                            "system_tray",
+                            format!("SystemTrayEmulator restart err: {err}"),
+                        );
+                        ended = Attempt::Failed;
+                        break;
                     }
                 }
             }
         }
 
-        Ok(())
+        // Free watcher names right away, so the next start can own them:
+        *self.watcher.write().await = None;
+        for (_, task) in watcher.item_watchers.lock().unwrap().drain() {
+            task.abort();
+        }
+        // Fails when the restart above already lost it:
+        let _ = Self::unserve_watcher(&arc_conn).await;
+        // After a takeover one of them is not ours anymore:
+        let _ = arc_conn.release_name(WATCHER_NAME).await;
+        let _ = arc_conn.release_name(KDE_WATCHER_NAME).await;
+        *self.unique_name.write().await = None;
+
+        Ok(ended)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        SignalFailures, SystemTrayEmulator, Watcher, MAX_SIGNAL_FAILURES, WATCHER_NAME,
+        WATCHER_PATH,
+    };
+    use futures_util::StreamExt;
+    use std::os::unix::net::UnixStream;
+    use std::sync::Arc;
+    use tokio::sync::RwLock;
+    use zbus::{Connection, ConnectionBuilder, Guid, MessageStream};
+
+    // What signal_item returns when no signal context can be made:
+    fn failed() -> zbus::Result<()> {
+        Err(zbus::Error::Failure("no signal context".to_string()))
+    }
+
+    #[test]
+    fn restarts_after_failures_in_a_row() {
+        let mut failures = SignalFailures::default();
+        for _ in 1..MAX_SIGNAL_FAILURES {
+            assert!(!failures.record(&failed()));
+        }
+        assert!(failures.record(&failed()));
+    }
+
+    #[test]
+    fn success_starts_over() {
+        let mut failures = SignalFailures::default();
+        for _ in 1..MAX_SIGNAL_FAILURES {
+            failures.record(&failed());
+        }
+        assert!(!failures.record(&Ok(())));
+        assert!(!failures.record(&failed()));
+    }
+
+    #[test]
+    fn counts_again_after_restart() {
+        let mut failures = SignalFailures::default();
+        for _ in 0..MAX_SIGNAL_FAILURES {
+            failures.record(&failed());
+        }
+        assert!(!failures.record(&failed()));
+    }
+
+    // Watcher's connection and the peer that sees its signals
+    async fn peers() -> (Connection, Connection) {
+        let (stream, peer_stream) = UnixStream::pair().unwrap();
+        let guid = Guid::generate();
+        let (connection, peer) = tokio::join!(
+            ConnectionBuilder::unix_stream(stream)
+                .server(&guid)
+                .p2p()
+                .build(),
+            ConnectionBuilder::unix_stream(peer_stream).p2p().build(),
+        );
+        (connection.unwrap(), peer.unwrap())
+    }
+
+    async fn watcher(connection: Connection, path: &str) -> Watcher {
+        Watcher::new(
+            None,
+            Arc::new(connection),
+            path,
+            Arc::new(RwLock::new(None)),
+        )
+        .await
+    }
+
+    // Services of StatusNotifierItemRegistered signals, until the marker's
+    async fn registered_until(signals: &mut MessageStream, marker: &str) -> Vec<String> {
+        let mut registered = Vec::new();
+        while let Some(Ok(message)) = signals.next().await {
+            let is_registered = message
+                .member()
+                .is_some_and(|v| v.as_str() == "StatusNotifierItemRegistered")
+                && message
+                    .interface()
+                    .is_some_and(|v| v.as_str() == WATCHER_NAME);
+            if !is_registered {
+                continue;
+            }
+
+            let service: String = message.body().unwrap();
+            if service == marker {
+                break;
+            }
+            registered.push(service);
+        }
+        registered
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn racing_registrations_signal_and_watch_once() {
+        let (connection, peer) = peers().await;
+        let mut signals = MessageStream::from(&peer);
+        let watcher = watcher(connection, WATCHER_PATH).await;
+
+        let services: Vec<String> = (0..20).map(|round| format!(":1.{}", 100 + round)).collect();
+        for service in &services {
+            let tasks: Vec<_> = (0..2)
+                .map(|_| {
+                    let (watcher, service) = (watcher.clone(), service.clone());
+                    tokio::spawn(async move { watcher.register_item(service).await })
+                })
+                .collect();
+            for task in tasks {
+                task.await.unwrap().unwrap();
+            }
+        }
+        // Its signal comes after all of the racing ones:
+        watcher.register_item(":1.99".to_string()).await.unwrap();
+
+        let mut registered = registered_until(&mut signals, ":1.99").await;
+        registered.sort();
+        assert_eq!(registered, services);
+        assert_eq!(watcher.item_watchers.lock().unwrap().len(), 21);
+        assert_eq!(watcher.item_list().await.len(), 21);
+    }
+
+    // Signals can't be sent from an invalid path, every round fails. Each
+    // name owns an item by name and one by custom path
+    async fn failing_watcher(names: usize) -> (Watcher, Vec<String>) {
+        let (connection, _) = peers().await;
+        let watcher = watcher(connection, "no signal path").await;
+        let names: Vec<String> = (0..names).map(|v| format!(":1.{}", 200 + v)).collect();
+        let mut items = watcher.items.write().await;
+        for name in &names {
+            items.insert(name.clone());
+            items.insert(format!("{name}/org/ayatana/NotificationItem/app"));
+        }
+        drop(items);
+        (watcher, names)
+    }
+
+    #[tokio::test]
+    async fn failed_signals_keep_tracking_gone_names() {
+        let (watcher, names) = failing_watcher(MAX_SIGNAL_FAILURES as usize).await;
+        let mut failures = SignalFailures::default();
+
+        for (round, name) in names.iter().enumerate().take(names.len() - 1) {
+            assert!(watcher.owner_gone(name, &mut failures).await.is_ok());
+            let left = (names.len() - round - 1) * 2;
+            assert_eq!(watcher.item_list().await.len(), left);
+        }
+    }
+
+    // Watcher currently served at the path is this one
+    async fn serves(watcher: &Watcher) -> bool {
+        let served = watcher
+            .conn
+            .object_server()
+            .interface::<_, Watcher>(WATCHER_PATH)
+            .await;
+        match served {
+            Ok(served) => Arc::ptr_eq(&served.get().await.items, &watcher.items),
+            Err(_) => false,
+        }
+    }
+
+    #[tokio::test]
+    async fn failure_limit_restarts_watcher() {
+        let limit = MAX_SIGNAL_FAILURES as usize;
+        let (watcher, names) = failing_watcher(limit * 2).await;
+        // Stale watcher at the path, the restart replaces it:
+        let stale = Watcher::new(
+            None,
+            watcher.conn.clone(),
+            WATCHER_PATH,
+            Arc::new(RwLock::new(None)),
+        )
+        .await;
+        SystemTrayEmulator::serve_watcher(&watcher.conn, &stale)
+            .await
+            .unwrap();
+        let mut failures = SignalFailures::default();
+
+        for name in &names[..limit] {
+            assert!(!serves(&watcher).await);
+            assert!(watcher.owner_gone(name, &mut failures).await.is_ok());
+        }
+        assert!(serves(&watcher).await);
+
+        // Nothing served anymore: the next restart fails, the loop stops
+        SystemTrayEmulator::unserve_watcher(&watcher.conn)
+            .await
+            .unwrap();
+        for name in &names[limit..names.len() - 1] {
+            assert!(watcher.owner_gone(name, &mut failures).await.is_ok());
+        }
+        let last = watcher.owner_gone(&names[names.len() - 1], &mut failures);
+        assert!(last.await.is_err());
+        assert!(watcher.item_list().await.is_empty());
     }
 }
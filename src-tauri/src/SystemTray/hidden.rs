@@ -0,0 +1,101 @@
+use crate::Bus::config_path;
+use crate::Log;
+use std::collections::HashSet;
+use std::fs;
+use std::sync::Mutex;
+
+// Apps hidden from the tray by stable id, loaded on first use. Kept in
+// tray-hidden.json so the choice survives restarts
+static HIDDEN: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+
+const HIDDEN_FILE: &str = "tray-hidden.json";
+
+pub fn is_hidden(id: &str) -> bool {
+    let mut hidden = HIDDEN.lock().unwrap();
+    hidden.get_or_insert_with(load).contains(id)
+}
+
+pub fn set_hidden(id: &str, hidden: bool) -> Result<(), String> {
+    if id.is_empty() {
+        return Err("Tray item has no app id to hide it by".to_string());
+    }
+
+    let mut set = HIDDEN.lock().unwrap();
+    let set = set.get_or_insert_with(load);
+    if !toggle(set, id, hidden) {
+        return Ok(());
+    }
+
+    save(set)
+}
+
+// Whether the set changed
+fn toggle(set: &mut HashSet<String>, id: &str, hidden: bool) -> bool {
+    match hidden {
+        true => set.insert(id.to_string()),
+        false => set.remove(id),
+    }
+}
+
+// Missing or broken file hides nothing
+fn load() -> HashSet<String> {
+    match fs::read_to_string(config_path(HIDDEN_FILE)) {
+        Ok(content) => parse(&content),
+        Err(_) => HashSet::new(),
+    }
+}
+
+fn parse(content: &str) -> HashSet<String> {
+    serde_json::from_str(content).unwrap_or_else(|err| {
+        Log::warn("system_tray", format!("Tray hidden apps err: {err}"));
+        HashSet::new()
+    })
+}
+
+fn save(set: &HashSet<String>) -> Result<(), String> {
+    let path = config_path(HIDDEN_FILE);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+
+    // Sorted, so the file diffs cleanly:
+    let mut ids: Vec<&String> = set.iter().collect();
+    ids.sort();
+    let content = serde_json::to_string_pretty(&ids).map_err(|e| e.to_string())?;
+    fs::write(path, content).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toggle_reports_changes() {
+        let mut set = HashSet::new();
+        assert!(toggle(&mut set, "telegram", true));
+        assert!(!toggle(&mut set, "telegram", true));
+        assert!(set.contains("telegram"));
+
+        assert!(toggle(&mut set, "telegram", false));
+        assert!(!toggle(&mut set, "telegram", false));
+        assert!(set.is_empty());
+    }
+
+    #[test]
+    fn parse_reads_ids() {
+        let set = parse(r#"["nm-applet", "telegram"]"#);
+        assert_eq!(set.len(), 2);
+        assert!(set.contains("nm-applet"));
+    }
+
+    #[test]
+    fn broken_file_hides_nothing() {
+        assert!(parse("{not json").is_empty());
+        assert!(parse(r#"{"telegram": true}"#).is_empty());
+    }
+
+    #[test]
+    fn empty_id_is_rejected() {
+        assert!(set_hidden("", true).is_err());
+    }
+}
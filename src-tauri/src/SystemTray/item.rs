@@ -0,0 +1,460 @@
+use super::emulator::SystemTrayEmulator;
+use super::{
+    hidden, is_subscribed, item_address, tooltip_text, TouriSystemTray, EMIT_EVENT,
+    FREEDESKTOP_ITEM_INTERFACE, ITEM_INTERFACE, WATCHER_PATH,
+};
+use crate::Emit;
+use crate::IconTheme::{find_with_extension, icon_data_url, is_icon_name, IconTheme};
+use crate::Log;
+use base64::Engine;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::Mutex;
+use zbus::fdo::{DBusProxy, PropertiesProxy};
+use zbus::names::{BusName, InterfaceName};
+use zbus::Connection;
+use zvariant::{OwnedValue, Value};
+
+// Registered item with the properties needed to show it
+#[derive(Clone, Serialize, Debug)]
+pub struct TrayItem {
+    service: String,
+    // Stable app key: Id of item, or process name when Id is empty:
+    id: String,
+    title: String,
+    // Title of ToolTip, empty when the item has none:
+    tooltip: String,
+    // "ApplicationStatus", "Communications", "SystemServices" or "Hardware":
+    category: String,
+    status: String,
+    icon_name: String,
+    // Icon as data url, from IconName or IconPixmap:
+    icon: Option<String>,
+    // Neither resolved, icon is the configured fallback:
+    icon_fallback: bool,
+    // User hid this app by id, see tray_set_hidden:
+    hidden: bool,
+}
+
+// Size icons are looked up with:
+const ICON_SIZE: u32 = 24;
+// Generic icon for items whose own icon doesn't resolve, so they aren't
+// invisible but still clickable:
+const DEFAULT_FALLBACK_ICON: &str = "application-x-executable";
+// Single item again after its icon, status, title or tooltip changed:
+pub const ITEM_EVENT: &str = "onTrayItemUpdate";
+
+// Configured fallback name, None for the default, empty disables it
+static FALLBACK_ICON: Mutex<Option<String>> = Mutex::new(None);
+
+pub fn set_fallback_icon(name: Option<String>) {
+    *FALLBACK_ICON.lock().unwrap() = name;
+}
+
+fn fallback_icon() -> String {
+    FALLBACK_ICON
+        .lock()
+        .unwrap()
+        .clone()
+        .unwrap_or_else(|| DEFAULT_FALLBACK_ICON.to_string())
+}
+
+impl TouriSystemTray {
+    // Complete item list with freshly resolved icons, sent right away.
+    // Narrowed to subscribed services, if any
+    pub async fn emit_full(&self) -> Result<Vec<TrayItem>, String> {
+        IconTheme::reload();
+        self.prewarm().await
+    }
+
+    // Resolves every icon (theme lookups, pixmap conversion) before the list
+    // goes out, so the frontend's first paint has them all. Keeps the theme
+    // cache, later item updates hit it
+    pub async fn prewarm(&self) -> Result<Vec<TrayItem>, String> {
+        let connection = self.connection().await?;
+        let emulator = self.emulator.read().await.clone();
+        let watcher_name = self.backend.read().await.watcher_name.clone();
+        let subscription = self.subscription.read().await.clone();
+
+        resync(
+            &connection,
+            emulator.as_deref(),
+            &watcher_name,
+            &subscription,
+            |items| {
+                let _ = Emit::emit(&self.app_handle, "system_tray", EMIT_EVENT, items);
+            },
+        )
+        .await
+    }
+
+    pub async fn items(&self) -> Result<Vec<TrayItem>, String> {
+        let connection = self.connection().await?;
+        let emulator = self.emulator.read().await.clone();
+        let watcher_name = self.backend.read().await.watcher_name.clone();
+
+        let services = registered_items(&connection, emulator.as_deref(), &watcher_name).await?;
+        Ok(fetch_items(&connection, &services).await)
+    }
+}
+
+// Runs on every (re)connect: the registry is read again and the complete
+// list sent, items of the old connection are never reused
+async fn resync(
+    connection: &Connection,
+    emulator: Option<&SystemTrayEmulator>,
+    watcher_name: &str,
+    subscription: &Option<HashSet<String>>,
+    emit: impl FnOnce(Vec<TrayItem>),
+) -> Result<Vec<TrayItem>, String> {
+    let services = registered_items(connection, emulator, watcher_name).await?;
+    let mut items = fetch_items(connection, &services).await;
+    items.retain(|item| is_subscribed(subscription, &item.service));
+    emit(items.clone());
+
+    Ok(items)
+}
+
+// Services from our emulator, or from the real watcher
+async fn registered_items(
+    connection: &Connection,
+    emulator: Option<&SystemTrayEmulator>,
+    watcher_name: &str,
+) -> Result<Vec<String>, String> {
+    if let Some(emulator) = emulator {
+        return Ok(emulator.registry().await.0);
+    }
+
+    if watcher_name.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let properties_proxy = PropertiesProxy::builder(connection)
+        .destination(watcher_name.to_string())
+        .and_then(|v| v.path(WATCHER_PATH))
+        .map_err(|e| e.to_string())?
+        .build()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let interface = InterfaceName::try_from(watcher_name).map_err(|e| e.to_string())?;
+    let items = properties_proxy
+        .get(interface, "RegisteredStatusNotifierItems")
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Vec::<String>::try_from(items).map_err(|e| e.to_string())
+}
+
+// Items that can't be read are logged and left out
+async fn fetch_items(connection: &Connection, services: &[String]) -> Vec<TrayItem> {
+    let mut items = Vec::new();
+
+    for service in services {
+        match fetch_item(connection, service).await {
+            Ok(item) => items.push(item),
+            Err(err) => Log::error(
+                "system_tray",
+                format!("TouriSystemTray item err: {service}: {err}"),
+            ),
+        }
+    }
+
+    items
+}
+
+pub(super) async fn fetch_item(connection: &Connection, service: &str) -> zbus::Result<TrayItem> {
+    let (name, path) = item_address(service);
+
+    let properties_proxy = PropertiesProxy::builder(connection)
+        .destination(name)?
+        .path(path)?
+        .build()
+        .await?;
+    let mut props = properties_proxy
+        .get_all(InterfaceName::from_static_str_unchecked(ITEM_INTERFACE))
+        .await
+        .unwrap_or_default();
+    if props.is_empty() {
+        props = properties_proxy
+            .get_all(InterfaceName::from_static_str_unchecked(
+                FREEDESKTOP_ITEM_INTERFACE,
+            ))
+            .await?;
+    }
+
+    let mut id = string(&props, "Id");
+    if id.is_empty() {
+        id = process_name(connection, name).await.unwrap_or_default();
+    }
+
+    let icon_name = string(&props, "IconName");
+    let (icon, icon_fallback) = resolve_icon(
+        &icon_name,
+        &string(&props, "IconThemePath"),
+        props.get("IconPixmap").map(|v| &**v),
+        &fallback_icon(),
+    );
+
+    let is_hidden = hidden::is_hidden(&id);
+
+    Ok(TrayItem {
+        service: service.to_string(),
+        id,
+        title: string(&props, "Title"),
+        tooltip: props
+            .get("ToolTip")
+            .map(|v| tooltip_text(v).0)
+            .unwrap_or_default(),
+        category: string(&props, "Category"),
+        status: string(&props, "Status"),
+        icon_name,
+        icon,
+        icon_fallback,
+        hidden: is_hidden,
+    })
+}
+
+// IconName, then IconPixmap, then the fallback name against the theme.
+// Flag is set when the fallback was used
+fn resolve_icon(
+    icon_name: &str,
+    theme_path: &str,
+    pixmap: Option<&Value>,
+    fallback: &str,
+) -> (Option<String>, bool) {
+    let icon = icon_from_name(icon_name, theme_path).or_else(|| pixmap.and_then(icon_from_pixmap));
+    if icon.is_some() {
+        return (icon, false);
+    }
+
+    let icon = icon_from_name(fallback, "");
+    let used = icon.is_some();
+    (icon, used)
+}
+
+// Apps may ship icons in their own IconThemePath, look there first
+fn icon_from_name(icon_name: &str, theme_path: &str) -> Option<String> {
+    if icon_name.is_empty() {
+        return None;
+    }
+
+    let path = Some(theme_path)
+        .filter(|v| !v.is_empty() && is_icon_name(icon_name))
+        .and_then(|v| find_with_extension(Path::new(v), icon_name))
+        .or_else(|| IconTheme::current().lookup(icon_name, ICON_SIZE))?;
+
+    icon_data_url(&path)
+}
+
+// IconPixmap is a(iiay) of ARGB32 in network byte order. The largest one is
+// taken, the frontend scales it down sharper on HiDPI than we scale it up
+fn icon_from_pixmap(value: &Value) -> Option<String> {
+    let pixmaps = Vec::<(i32, i32, Vec<u8>)>::try_from(value.clone()).ok()?;
+    let (width, height, argb) = pixmaps
+        .into_iter()
+        .filter(|(width, height, data)| {
+            *width > 0 && *height > 0 && data.len() == *width as usize * *height as usize * 4
+        })
+        .max_by_key(|(width, height, _)| *width as i64 * *height as i64)?;
+
+    let rgba: Vec<u8> = argb
+        .chunks_exact(4)
+        .flat_map(|v| [v[1], v[2], v[3], v[0]])
+        .collect();
+    let image = image::RgbaImage::from_raw(width as u32, height as u32, rgba)?;
+
+    let mut png = Cursor::new(Vec::new());
+    image.write_to(&mut png, image::ImageFormat::Png).ok()?;
+    let data = base64::engine::general_purpose::STANDARD.encode(png.into_inner());
+
+    Some(format!("data:image/png;base64,{data}"))
+}
+
+fn string(props: &HashMap<String, OwnedValue>, key: &str) -> String {
+    match props.get(key).map(|v| &**v) {
+        Some(Value::Str(v)) => v.to_string(),
+        _ => String::new(),
+    }
+}
+
+// Fallback id for items without Id: comm of the owning process
+pub(super) async fn process_name(connection: &Connection, name: &str) -> Option<String> {
+    let dbus_proxy = DBusProxy::new(connection).await.ok()?;
+    let pid = dbus_proxy
+        .get_connection_unix_process_id(BusName::try_from(name).ok()?)
+        .await
+        .ok()?;
+
+    let comm = fs::read_to_string(format!("/proc/{pid}/comm")).ok()?;
+    Some(comm.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::KDE_WATCHER_NAME;
+    use super::*;
+    use std::env;
+    use std::os::unix::net::UnixStream;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn fallback_png() -> String {
+        let path = env::temp_dir().join(format!("touri-fallback-{}.png", std::process::id()));
+        image::RgbaImage::new(1, 1).save(&path).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn no_usable_icon_uses_fallback() {
+        let fallback = fallback_png();
+        let empty_pixmap = Value::from(Vec::<(i32, i32, Vec<u8>)>::new());
+
+        let (icon, used) = resolve_icon("", "", None, &fallback);
+        assert!(used);
+        assert!(icon.unwrap().starts_with("data:image/png;base64,"));
+
+        let (icon, used) = resolve_icon("", "", Some(&empty_pixmap), &fallback);
+        assert!(used);
+        assert!(icon.is_some());
+    }
+
+    #[test]
+    fn own_icon_wins_over_fallback() {
+        let fallback = fallback_png();
+        let pixmap = Value::from(vec![(1, 1, vec![255u8, 0, 0, 0])]);
+
+        let (icon, used) = resolve_icon("", "", Some(&pixmap), &fallback);
+        assert!(!used);
+        assert!(icon.is_some());
+    }
+
+    #[test]
+    fn unresolvable_fallback_leaves_no_icon() {
+        let (icon, used) = resolve_icon("", "", None, "");
+        assert_eq!((icon, used), (None, false));
+
+        let (icon, used) = resolve_icon("", "", None, "/nonexistent/touri-icon.png");
+        assert_eq!((icon, used), (None, false));
+    }
+
+    // Watcher on the other end of a peer connection, counts registry reads
+    struct FakeWatcher {
+        items: Vec<String>,
+        reads: Arc<AtomicU32>,
+    }
+
+    #[zbus::dbus_interface(name = "org.kde.StatusNotifierWatcher")]
+    impl FakeWatcher {
+        #[dbus_interface(property)]
+        async fn registered_status_notifier_items(&self) -> Vec<String> {
+            self.reads.fetch_add(1, Ordering::SeqCst);
+            self.items.clone()
+        }
+    }
+
+    struct FakeItem(&'static str);
+
+    #[zbus::dbus_interface(name = "org.kde.StatusNotifierItem")]
+    impl FakeItem {
+        #[dbus_interface(property)]
+        async fn id(&self) -> String {
+            self.0.to_string()
+        }
+
+        #[dbus_interface(property)]
+        async fn title(&self) -> String {
+            self.0.to_uppercase()
+        }
+    }
+
+    // Tray side of a fresh connection, the other side serves the watcher
+    // with the given items. Served by the builder, so calls never race the
+    // object server start
+    async fn connect(ids: &[&'static str], reads: &Arc<AtomicU32>) -> (Connection, Connection) {
+        let (watcher_stream, tray_stream) = UnixStream::pair().unwrap();
+        let guid = zbus::Guid::generate();
+        let fake = FakeWatcher {
+            items: ids.iter().map(|id| format!(":1.1/item/{id}")).collect(),
+            reads: reads.clone(),
+        };
+        let mut watcher = zbus::ConnectionBuilder::unix_stream(watcher_stream)
+            .server(&guid)
+            .p2p()
+            .serve_at(WATCHER_PATH, fake)
+            .unwrap();
+        for id in ids {
+            watcher = watcher
+                .serve_at(format!("/item/{id}"), FakeItem(id))
+                .unwrap();
+        }
+
+        let (watcher, tray) = tokio::join!(
+            watcher.build(),
+            zbus::ConnectionBuilder::unix_stream(tray_stream)
+                .p2p()
+                .build(),
+        );
+        (watcher.unwrap(), tray.unwrap())
+    }
+
+    fn ids(items: &[TrayItem]) -> Vec<&str> {
+        items.iter().map(|item| item.id.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn reconnect_reads_registry_and_sends_full_list() {
+        let reads = Arc::new(AtomicU32::new(0));
+        let mut emitted: Vec<Vec<TrayItem>> = Vec::new();
+
+        let (watcher, tray) = connect(&["a"], &reads).await;
+        let items = resync(&tray, None, KDE_WATCHER_NAME, &None, |v| emitted.push(v))
+            .await
+            .unwrap();
+        assert_eq!(ids(&items), ["a"]);
+        assert_eq!(items[0].title, "A");
+
+        // Bus goes away, an item registered meanwhile:
+        drop(watcher);
+        let stale = tokio::time::timeout(
+            Duration::from_secs(1),
+            resync(&tray, None, KDE_WATCHER_NAME, &None, |v| emitted.push(v)),
+        )
+        .await;
+        assert!(!matches!(stale, Ok(Ok(_))));
+
+        let (_watcher, tray) = connect(&["a", "b"], &reads).await;
+        let subscription = Some(HashSet::from([":1.1/item/b".to_string()]));
+        resync(&tray, None, KDE_WATCHER_NAME, &None, |v| emitted.push(v))
+            .await
+            .unwrap();
+        resync(&tray, None, KDE_WATCHER_NAME, &subscription, |v| {
+            emitted.push(v)
+        })
+        .await
+        .unwrap();
+
+        // Once per live connection, the dead one sent nothing:
+        assert_eq!(reads.load(Ordering::SeqCst), 3);
+        let emitted: Vec<Vec<&str>> = emitted.iter().map(|v| ids(v)).collect();
+        assert_eq!(emitted, [vec!["a"], vec!["a", "b"], vec!["b"]]);
+    }
+
+    #[tokio::test]
+    async fn no_watcher_sends_empty_list() {
+        let reads = Arc::new(AtomicU32::new(0));
+        let (_watcher, tray) = connect(&["a"], &reads).await;
+        let mut emitted = None;
+
+        let items = resync(&tray, None, "", &None, |v| emitted = Some(v))
+            .await
+            .unwrap();
+        assert!(items.is_empty());
+        assert_eq!(emitted.map(|v| v.len()), Some(0));
+        assert_eq!(reads.load(Ordering::SeqCst), 0);
+    }
+}
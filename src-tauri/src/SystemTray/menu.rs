@@ -0,0 +1,376 @@
+use super::{item_address, TouriSystemTray};
+use crate::DBusBridge::value_to_json;
+use crate::Emit;
+use crate::Log;
+use futures_util::{Stream, StreamExt};
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio_util::sync::CancellationToken;
+use zbus::names::BusName;
+use zbus::Proxy;
+use zvariant::{OwnedObjectPath, OwnedValue, Value};
+
+const MENU_INTERFACE: &str = "com.canonical.dbusmenu";
+
+// Root node of GetLayout reply: (id, properties, children)
+type Layout = (i32, HashMap<String, OwnedValue>, Vec<OwnedValue>);
+pub const MENU_EVENT: &str = "onTrayMenuUpdate";
+
+#[derive(Clone, Serialize, Debug)]
+pub struct TrayMenu {
+    service: String,
+    revision: u32,
+    layout: TrayMenuItem,
+}
+
+// DBusMenu layout node (ia{sv}av), properties as JSON:
+#[derive(Clone, Serialize, Debug)]
+pub struct TrayMenuItem {
+    id: i32,
+    properties: JsonValue,
+    // Label without access key underscores, "_File" shows as "File":
+    label: String,
+    // Missing properties use the dbusmenu defaults:
+    enabled: bool,
+    visible: bool,
+    separator: bool,
+    // Checkable items: "checkmark", "radio" or empty, state 1 on, 0 off, -1 unknown
+    toggle_type: String,
+    toggle_state: i32,
+    children: Vec<TrayMenuItem>,
+}
+
+impl TrayMenuItem {
+    fn new(id: i32, properties: JsonValue, children: Vec<TrayMenuItem>) -> Self {
+        let toggle_type = properties
+            .get("toggle-type")
+            .and_then(|v| v.as_str())
+            .unwrap_or_default()
+            .to_string();
+        let toggle_state = properties
+            .get("toggle-state")
+            .and_then(|v| v.as_i64())
+            .unwrap_or(-1) as i32;
+
+        let label = properties
+            .get("label")
+            .and_then(|v| v.as_str())
+            .map(strip_access_key)
+            .unwrap_or_default();
+        let flag = |key: &str| properties.get(key).and_then(|v| v.as_bool());
+        let separator = properties.get("type").and_then(|v| v.as_str()) == Some("separator");
+
+        Self {
+            id,
+            label,
+            enabled: flag("enabled").unwrap_or(true),
+            visible: flag("visible").unwrap_or(true),
+            separator,
+            properties,
+            toggle_type,
+            toggle_state,
+            children,
+        }
+    }
+}
+
+impl TouriSystemTray {
+    // Fetch menu of item again, and send it to frontend
+    pub async fn refresh_menu(&self, service: &str) -> Result<TrayMenu, String> {
+        let menu = self.fetch_menu(service).await?;
+        let _ = Emit::emit(&self.app_handle, "system_tray", MENU_EVENT, menu.clone());
+        Ok(menu)
+    }
+
+    // Menu is shown: follow its LayoutUpdated until it is closed
+    pub async fn open_menu(self: Arc<Self>, service: String) -> Result<TrayMenu, String> {
+        let menu = self.fetch_menu(&service).await?;
+
+        if let Some(task) = self.menu_watchers.lock().unwrap().get(&service) {
+            if !task.is_finished() {
+                return Ok(menu);
+            }
+        }
+
+        let proxy = self.menu_proxy(&service).await?;
+        let mut layout_updated = proxy
+            .receive_signal("LayoutUpdated")
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let tray = self.clone();
+        let watched_service = service.clone();
+        let shutdown = self.shutdown.token();
+        let task = tokio::spawn(async move {
+            follow_layout(&mut layout_updated, &shutdown, || async {
+                tray.refresh_menu(&watched_service).await.map(|_| ())
+            })
+            .await;
+        });
+
+        if let Some(previous) = self.menu_watchers.lock().unwrap().insert(service, task) {
+            previous.abort();
+        }
+
+        Ok(menu)
+    }
+
+    // Click menu item, then send the menu again for new toggle states
+    pub async fn activate_menu_item(&self, service: &str, id: i32) -> Result<TrayMenu, String> {
+        let proxy = self.menu_proxy(service).await?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|v| v.as_secs() as u32)
+            .unwrap_or_default();
+
+        proxy
+            .call_method("Event", &(id, "clicked", Value::I32(0), timestamp))
+            .await
+            .map_err(|e| e.to_string())?;
+
+        self.refresh_menu(service).await
+    }
+
+    pub fn close_menu(&self, service: &str) {
+        if let Some(task) = self.menu_watchers.lock().unwrap().remove(service) {
+            task.abort();
+        }
+    }
+
+    async fn menu_proxy(&self, service: &str) -> Result<Proxy<'static>, String> {
+        let connection = self.connection().await?;
+        let (name, _) = item_address(service);
+        let name = BusName::try_from(name.to_string()).map_err(|e| e.to_string())?;
+
+        // Item tells where its menu lives:
+        let menu_path = self.item_property(service, "Menu").await?;
+        let menu_path = OwnedObjectPath::try_from(menu_path).map_err(|e| e.to_string())?;
+
+        Proxy::new(&connection, name, menu_path.into_inner(), MENU_INTERFACE)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn fetch_menu(&self, service: &str) -> Result<TrayMenu, String> {
+        let proxy = self.menu_proxy(service).await?;
+
+        // Whole tree from the root, with all properties:
+        let (revision, (id, properties, children)): (u32, Layout) = proxy
+            .call("GetLayout", &(0i32, -1i32, Vec::<String>::new()))
+            .await
+            .map_err(|e| format!("Tray item {service} menu is gone: {e}"))?;
+
+        Ok(TrayMenu {
+            service: service.to_string(),
+            revision,
+            layout: TrayMenuItem::new(
+                id,
+                properties_to_json(&properties),
+                children.iter().filter_map(|v| menu_item(v)).collect(),
+            ),
+        })
+    }
+}
+
+// Menu again on every LayoutUpdated, until the stream ends or on shutdown
+async fn follow_layout<S, F, Fut>(
+    layout_updated: &mut S,
+    shutdown: &CancellationToken,
+    mut refresh: F,
+) where
+    S: Stream + Unpin,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<(), String>>,
+{
+    while tokio::select! {
+        signal = layout_updated.next() => signal.is_some(),
+        _ = shutdown.cancelled() => false,
+    } {
+        // Item is gone, nothing to follow anymore:
+        if let Err(err) = refresh().await {
+            Log::error("system_tray", format!("TouriSystemTray menu err: {err}"));
+            break;
+        }
+    }
+}
+
+fn properties_to_json(properties: &HashMap<String, OwnedValue>) -> JsonValue {
+    JsonValue::Object(
+        properties
+            .iter()
+            .map(|(key, value)| (key.clone(), value_to_json(value)))
+            .collect(),
+    )
+}
+
+// "_" marks the access key, "__" is a literal underscore
+fn strip_access_key(label: &str) -> String {
+    let mut result = String::with_capacity(label.len());
+    let mut chars = label.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '_' {
+            result.push(c);
+        } else if chars.peek() == Some(&'_') {
+            result.push('_');
+            chars.next();
+        }
+    }
+    result
+}
+
+fn menu_item(value: &Value) -> Option<TrayMenuItem> {
+    let fields = match value {
+        Value::Value(value) => return menu_item(value),
+        Value::Structure(structure) => structure.fields(),
+        _ => return None,
+    };
+
+    let id = match fields.first() {
+        Some(Value::I32(id)) => *id,
+        _ => return None,
+    };
+
+    let properties = match fields.get(1) {
+        Some(value @ Value::Dict(_)) => value_to_json(value),
+        _ => JsonValue::Object(Default::default()),
+    };
+
+    let children = match fields.get(2) {
+        Some(Value::Array(children)) => children.iter().filter_map(menu_item).collect(),
+        _ => Vec::new(),
+    };
+
+    Some(TrayMenuItem::new(id, properties, children))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{follow_layout, menu_item, strip_access_key, TrayMenuItem};
+    use futures_util::stream;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use tokio_util::sync::CancellationToken;
+    use zvariant::{StructureBuilder, Value};
+
+    fn node(id: i32, label: &str, children: Vec<Value<'static>>) -> Value<'static> {
+        let mut properties = HashMap::new();
+        properties.insert("label".to_string(), Value::from(label.to_string()));
+        properties.insert(
+            "toggle-type".to_string(),
+            Value::from("checkmark".to_string()),
+        );
+        properties.insert("toggle-state".to_string(), Value::I32(1));
+
+        StructureBuilder::new()
+            .add_field(id)
+            .add_field(properties)
+            .add_field(children)
+            .build()
+            .into()
+    }
+
+    #[test]
+    fn access_keys_are_stripped() {
+        assert_eq!(strip_access_key("_File"), "File");
+        assert_eq!(strip_access_key("Save __as"), "Save _as");
+        assert_eq!(strip_access_key("Plain"), "Plain");
+    }
+
+    #[test]
+    fn missing_properties_use_defaults() {
+        let item = TrayMenuItem::new(3, json!({}), Vec::new());
+        let item = serde_json::to_value(item).unwrap();
+        assert_eq!(item["enabled"], true);
+        assert_eq!(item["visible"], true);
+        assert_eq!(item["separator"], false);
+        assert_eq!(item["toggle_type"], "");
+        assert_eq!(item["toggle_state"], -1);
+    }
+
+    #[test]
+    fn layout_is_parsed_recursively() {
+        let child = Value::Value(Box::new(node(2, "_Mute", Vec::new())));
+        let root = menu_item(&node(0, "", vec![child])).unwrap();
+        let root = serde_json::to_value(root).unwrap();
+
+        let child = &root["children"][0];
+        assert_eq!(child["id"], 2);
+        assert_eq!(child["label"], "Mute");
+        assert_eq!(child["toggle_type"], "checkmark");
+        assert_eq!(child["toggle_state"], 1);
+        assert_eq!(child["properties"]["toggle-type"], "checkmark");
+    }
+
+    #[test]
+    fn malformed_node_is_skipped() {
+        assert!(menu_item(&Value::from("not a node")).is_none());
+    }
+
+    #[tokio::test]
+    async fn layout_updated_refreshes_menu() {
+        let refreshes = AtomicU32::new(0);
+        let mut layout_updated = stream::iter([(), (), ()]);
+
+        follow_layout(&mut layout_updated, &CancellationToken::new(), || async {
+            refreshes.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        })
+        .await;
+
+        assert_eq!(refreshes.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn gone_menu_stops_following() {
+        let refreshes = AtomicU32::new(0);
+        let mut layout_updated = stream::iter([(), (), ()]);
+
+        follow_layout(&mut layout_updated, &CancellationToken::new(), || async {
+            refreshes.fetch_add(1, Ordering::SeqCst);
+            Err("menu is gone".to_string())
+        })
+        .await;
+
+        assert_eq!(refreshes.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn shutdown_stops_following() {
+        let shutdown = CancellationToken::new();
+        shutdown.cancel();
+        let mut layout_updated = stream::pending::<()>();
+
+        follow_layout(&mut layout_updated, &shutdown, || async { Ok(()) }).await;
+    }
+
+    #[test]
+    fn checkable_items_carry_toggle_state() {
+        let toggle = |properties| {
+            let item = serde_json::to_value(TrayMenuItem::new(1, properties, Vec::new())).unwrap();
+            (item["toggle_type"].clone(), item["toggle_state"].clone())
+        };
+
+        assert_eq!(
+            toggle(json!({"toggle-type": "radio", "toggle-state": 0})),
+            (json!("radio"), json!(0))
+        );
+        assert_eq!(
+            toggle(json!({"toggle-type": "checkmark", "toggle-state": 1})),
+            (json!("checkmark"), json!(1))
+        );
+        // State missing or of the wrong type is unknown:
+        assert_eq!(
+            toggle(json!({"toggle-type": "checkmark"})),
+            (json!("checkmark"), json!(-1))
+        );
+        assert_eq!(
+            toggle(json!({"toggle-type": "radio", "toggle-state": "on"})),
+            (json!("radio"), json!(-1))
+        );
+    }
+}
@@ -1,39 +1,479 @@
+pub mod commands;
 mod emulator;
+mod hidden;
+mod item;
+mod menu;
 
-use futures_util::FutureExt;
-use std::{sync::Arc, thread};
+pub use item::TrayItem;
+pub use menu::TrayMenu;
+
+use crate::Bus::{keep_connected, Attempt, Availability, BusConfig, ReconnectPolicy, SharedBus};
+use crate::DBusBridge::value_to_json;
+use crate::Emit;
+use crate::Log;
+use crate::Shutdown::Shutdown;
+use futures_util::{FutureExt, StreamExt};
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, Wry};
 use tokio::join;
-use zbus::fdo::DBusProxy;
-use zbus::names::BusName;
+use tokio::sync::RwLock;
+use zbus::fdo::{DBusProxy, PropertiesProxy};
+use zbus::names::{BusName, InterfaceName};
 use zbus::{Connection, Result};
+use zvariant::{DynamicType, OwnedValue, Value};
+
+const WATCHER_NAME: &str = "org.freedesktop.StatusNotifierWatcher";
+const KDE_WATCHER_NAME: &str = "org.kde.StatusNotifierWatcher";
+const WATCHER_PATH: &str = "/StatusNotifierWatcher";
+pub const EMIT_EVENT: &str = "onTrayItemsUpdate";
+const ITEM_INTERFACE: &str = "org.kde.StatusNotifierItem";
+// Some apps export the item only under the freedesktop name:
+const FREEDESKTOP_ITEM_INTERFACE: &str = "org.freedesktop.StatusNotifierItem";
+const ITEM_PATH: &str = "/StatusNotifierItem";
+
+pub struct TouriSystemTray {
+    app_handle: AppHandle<Wry>,
+    backend: RwLock<TrayBackend>,
+    emulator: RwLock<Option<Arc<emulator::SystemTrayEmulator>>>,
+    connection: RwLock<Option<Connection>>,
+    // LayoutUpdated listeners of shown menus by service:
+    menu_watchers: Mutex<HashMap<String, tokio::task::JoinHandle<()>>>,
+    // Tooltip falls back to app name (Id or process) when ToolTip and Title are empty:
+    tooltip_app_fallback: RwLock<bool>,
+    // Services items are emitted for, None emits all:
+    subscription: Subscription,
+    bus: BusConfig,
+    shared_bus: Arc<SharedBus>,
+    reconnect: ReconnectPolicy,
+    availability: Availability,
+    shutdown: Arc<Shutdown>,
+}
+
+// Which watcher serves tray items: "real", "emulator" or "none"
+#[derive(Clone, Serialize, Debug)]
+pub struct TrayBackend {
+    backend: String,
+    watcher_name: String,
+}
 
-pub struct TouriSystemTray {}
+impl TrayBackend {
+    fn new(backend: &str, watcher_name: &str) -> Self {
+        Self {
+            backend: backend.to_string(),
+            watcher_name: watcher_name.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Debug)]
+pub struct TrayTooltip {
+    title: String,
+    description: String,
+    // Where title comes from: "tooltip", "title", "app" or "none"
+    source: String,
+}
+
+// Why a debug property read failed, so the frontend can tell cases apart
+#[derive(Clone, Serialize, Debug)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum TrayPropertyError {
+    NotStarted(String),
+    UnknownService(String),
+    UnknownProperty(String),
+    Failed(String),
+}
+
+#[derive(Clone, Serialize, Debug)]
+pub struct TrayDebugState {
+    items: Vec<String>,
+    hosts: Vec<String>,
+    emulating: bool,
+    backend: TrayBackend,
+}
 
 impl TouriSystemTray {
-    pub fn new() -> Arc<Self> {
-        // Create instance and create Thread:
-        let instance = Arc::new(Self {});
+    pub fn new(
+        app_handle: AppHandle<Wry>,
+        shutdown: Arc<Shutdown>,
+        bus: BusConfig,
+        shared_bus: Arc<SharedBus>,
+        reconnect: ReconnectPolicy,
+    ) -> Arc<Self> {
+        // Create instance and create task:
+        let instance = Arc::new(Self {
+            app_handle,
+            backend: RwLock::new(TrayBackend::new("none", "")),
+            emulator: RwLock::new(None),
+            connection: RwLock::new(None),
+            menu_watchers: Mutex::new(HashMap::new()),
+            tooltip_app_fallback: RwLock::new(true),
+            subscription: Arc::new(RwLock::new(None)),
+            bus,
+            shared_bus,
+            reconnect,
+            availability: Availability::default(),
+            shutdown,
+        });
 
+        // Session bus can restart, items are read again on reconnect:
         let cloned_instance = instance.clone();
-        thread::spawn(move || {
-            let runtime = tokio::runtime::Runtime::new().unwrap();
-            runtime.block_on(async move {
-                if let Err(err) = cloned_instance.start().await {
-                    eprintln!("TouriSystemTray Err: {}", err);
-                }
-            });
+        instance.shutdown.spawn(async move {
+            let shutdown = cloned_instance.shutdown.token();
+            keep_connected(
+                "system_tray",
+                cloned_instance.reconnect.clone(),
+                &cloned_instance.availability,
+                &shutdown,
+                || cloned_instance.session(),
+            )
+            .await;
         });
 
         instance
     }
 
+    // Host and emulator reconnect apart, either can give up
+    pub async fn available(&self) -> bool {
+        let emulator = self.emulator.read().await.clone();
+        let emulator_available = match emulator {
+            Some(emulator) => emulator.available().await,
+            None => true,
+        };
+        self.availability.available().await && emulator_available
+    }
+
+    // Retry the bus after the reconnect policy gave up
+    pub async fn restart(&self) -> std::result::Result<(), String> {
+        let emulator = self.emulator.read().await.clone();
+        if let Some(emulator) = emulator {
+            if !emulator.available().await {
+                return emulator.restart().await;
+            }
+        }
+        self.availability.restart().await
+    }
+
+    // One connection, until the bus drops it
+    async fn session(&self) -> Attempt {
+        let result = self.start().await;
+        self.disconnect().await;
+
+        match result {
+            Ok(()) if self.shutdown.token().is_cancelled() => Attempt::Finished,
+            Ok(()) => {
+                Log::warn(
+                    "system_tray",
+                    "TouriSystemTray: bus connection lost, reconnecting",
+                );
+                Attempt::Dropped
+            }
+            Err(err) => {
+                Log::error("system_tray", format!("TouriSystemTray Err: {err}"));
+                Attempt::Failed
+            }
+        }
+    }
+
+    // Items and menus of the old connection are stale, frontend drops them
+    // until the next start sends the list again. The emulator has its own
+    // connection and is kept
+    async fn disconnect(&self) {
+        if let Some(connection) = self.connection.write().await.take() {
+            self.shared_bus.invalidate(&self.bus, &connection).await;
+        }
+        for (_, task) in self.menu_watchers.lock().unwrap().drain() {
+            task.abort();
+        }
+        if self.emulator.read().await.is_none() {
+            self.set_backend(TrayBackend::new("none", "")).await;
+        }
+        let _ = Emit::emit(
+            &self.app_handle,
+            "system_tray",
+            EMIT_EVENT,
+            Vec::<TrayItem>::new(),
+        );
+    }
+
+    pub async fn debug_state(&self) -> TrayDebugState {
+        let backend = self.backend.read().await.clone();
+        match self.emulator.read().await.as_ref() {
+            Some(emulator) => {
+                let (items, hosts) = emulator.registry().await;
+                TrayDebugState {
+                    items,
+                    hosts,
+                    emulating: true,
+                    backend,
+                }
+            }
+            None => TrayDebugState {
+                items: Vec::new(),
+                hosts: Vec::new(),
+                emulating: false,
+                backend,
+            },
+        }
+    }
+
+    pub async fn backend(&self) -> TrayBackend {
+        self.backend.read().await.clone()
+    }
+
+    async fn set_backend(&self, backend: TrayBackend) {
+        let _ = Emit::emit(
+            &self.app_handle,
+            "system_tray",
+            "onTrayBackendChosen",
+            backend.clone(),
+        );
+        *self.backend.write().await = backend;
+    }
+
+    // Only the emulator registry can be cleared, a real watcher is not ours
+    pub async fn clear_items(&self) -> std::result::Result<Vec<String>, String> {
+        match self.emulator.read().await.as_ref() {
+            Some(emulator) => emulator.clear_items().await.map_err(|e| e.to_string()),
+            None => Err("Tray emulator is not running".to_string()),
+        }
+    }
+
+    // Services not registered (yet) just never match
+    pub async fn subscribe(&self, services: Vec<String>) {
+        *self.subscription.write().await = Some(services.into_iter().collect());
+    }
+
+    pub async fn subscribe_all(&self) {
+        *self.subscription.write().await = None;
+    }
+
+    pub async fn set_tooltip_app_fallback(&self, enabled: bool) {
+        *self.tooltip_app_fallback.write().await = enabled;
+    }
+
+    // Hides or unhides an app by its stable id. Items stay in the list,
+    // flagged, so settings can unhide them
+    pub async fn set_hidden(&self, app_id: &str, hidden: bool) -> std::result::Result<(), String> {
+        hidden::set_hidden(app_id, hidden)?;
+        let _ = self.emit_full().await;
+        Ok(())
+    }
+
+    // Icon name for items without a usable icon, None resets the default,
+    // empty disables it. Re-emits so the change shows right away
+    pub async fn set_fallback_icon(&self, name: Option<String>) {
+        item::set_fallback_icon(name);
+        let _ = self.emit_full().await;
+    }
+
+    // Fresh ToolTip of item, apps often update it lazily. Items without
+    // one get Title, then app name, so hovering always shows something
+    pub async fn tooltip(&self, service: &str) -> std::result::Result<TrayTooltip, String> {
+        let tooltip = self.item_property(service, "ToolTip").await.ok();
+        let text = tooltip.as_deref().map(tooltip_text).unwrap_or_default();
+
+        let mut title = String::new();
+        if text.0.is_empty() {
+            let value = self.item_property(service, "Title").await?;
+            title = value.downcast_ref::<str>().unwrap_or_default().to_string();
+        }
+
+        let mut app = String::new();
+        if text.0.is_empty() && title.is_empty() && *self.tooltip_app_fallback.read().await {
+            app = self.app_name(service).await;
+        }
+
+        Ok(pick_tooltip(text, title, app))
+    }
+
+    // Id of item, or process name of its owner like TrayItem.id
+    async fn app_name(&self, service: &str) -> String {
+        if let Ok(id) = self.item_property(service, "Id").await {
+            if let Some(id) = id.downcast_ref::<str>().filter(|v| !v.is_empty()) {
+                return id.to_string();
+            }
+        }
+
+        match self.connection().await {
+            Ok(connection) => item::process_name(&connection, item_address(service).0)
+                .await
+                .unwrap_or_default(),
+            Err(_) => String::new(),
+        }
+    }
+
+    // Any StatusNotifierItem property as JSON, for diagnosing odd items
+    pub async fn property_json(
+        &self,
+        service: &str,
+        property: &str,
+    ) -> std::result::Result<JsonValue, TrayPropertyError> {
+        let connection = self
+            .connection()
+            .await
+            .map_err(TrayPropertyError::NotStarted)?;
+        let (name, path) = item_address(service);
+
+        let properties_proxy = PropertiesProxy::builder(&connection)
+            .destination(name)
+            .and_then(|v| v.path(path))
+            .map_err(|e| TrayPropertyError::UnknownService(format!("{service}: {e}")))?
+            .build()
+            .await
+            .map_err(|e| TrayPropertyError::Failed(e.to_string()))?;
+
+        let value = properties_proxy
+            .get(
+                InterfaceName::from_static_str_unchecked(ITEM_INTERFACE),
+                property,
+            )
+            .await
+            .map_err(|e| match e {
+                // Qt items answer unknown properties with InvalidArgs
+                zbus::fdo::Error::UnknownProperty(_) | zbus::fdo::Error::InvalidArgs(_) => {
+                    TrayPropertyError::UnknownProperty(format!("{property}: {e}"))
+                }
+                zbus::fdo::Error::ServiceUnknown(_)
+                | zbus::fdo::Error::NameHasNoOwner(_)
+                | zbus::fdo::Error::UnknownObject(_) => {
+                    TrayPropertyError::UnknownService(format!("{service}: {e}"))
+                }
+                e => TrayPropertyError::Failed(e.to_string()),
+            })?;
+
+        Ok(value_to_json(&value))
+    }
+
+    // Click on item at screen position. Items that only have a menu fail
+    // Activate, their menu is opened and returned instead
+    pub async fn activate(
+        self: Arc<Self>,
+        service: String,
+        x: i32,
+        y: i32,
+        secondary: bool,
+    ) -> std::result::Result<Option<TrayMenu>, String> {
+        let method = if secondary {
+            "SecondaryActivate"
+        } else {
+            "Activate"
+        };
+
+        match self.call_item(&service, method, &(x, y)).await {
+            Ok(()) => Ok(None),
+            Err(err) => {
+                Log::warn(
+                    "system_tray",
+                    format!("TouriSystemTray {method} err: {service}: {err}"),
+                );
+                self.open_menu(service).await.map(Some)
+            }
+        }
+    }
+
+    // Scroll over item, volume applets change volume by delta
+    pub async fn scroll(
+        &self,
+        service: &str,
+        delta: i32,
+        orientation: &str,
+    ) -> std::result::Result<(), String> {
+        if orientation != "vertical" && orientation != "horizontal" {
+            return Err(format!(
+                "Unknown orientation {orientation}, expected vertical or horizontal"
+            ));
+        }
+
+        self.call_item(service, "Scroll", &(delta, orientation))
+            .await
+    }
+
+    async fn call_item<B>(
+        &self,
+        service: &str,
+        method: &str,
+        body: &B,
+    ) -> std::result::Result<(), String>
+    where
+        B: Serialize + DynamicType,
+    {
+        let connection = self.connection().await?;
+        let (name, path) = item_address(service);
+
+        let result = connection
+            .call_method(Some(name), path, Some(ITEM_INTERFACE), method, body)
+            .await;
+        if result.is_ok() {
+            return Ok(());
+        }
+
+        connection
+            .call_method(
+                Some(name),
+                path,
+                Some(FREEDESKTOP_ITEM_INTERFACE),
+                method,
+                body,
+            )
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    async fn connection(&self) -> std::result::Result<Connection, String> {
+        self.connection
+            .read()
+            .await
+            .clone()
+            .ok_or_else(|| "Tray is not started".to_string())
+    }
+
+    async fn item_property(
+        &self,
+        service: &str,
+        property: &str,
+    ) -> std::result::Result<OwnedValue, String> {
+        let connection = self.connection().await?;
+        let (name, path) = item_address(service);
+
+        let properties_proxy = PropertiesProxy::builder(&connection)
+            .destination(name)
+            .and_then(|v| v.path(path))
+            .map_err(|e| e.to_string())?
+            .build()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let value = properties_proxy
+            .get(
+                InterfaceName::from_static_str_unchecked(ITEM_INTERFACE),
+                property,
+            )
+            .await;
+        if let Ok(value) = value {
+            return Ok(value);
+        }
+
+        properties_proxy
+            .get(
+                InterfaceName::from_static_str_unchecked(FREEDESKTOP_ITEM_INTERFACE),
+                property,
+            )
+            .await
+            .map_err(|e| format!("Tray item {service} is gone: {e}"))
+    }
+
     pub async fn start(&self) -> Result<()> {
         // Try find org.freedesktop.StatusNotifierWatcher or org.kded.StatusNotifierWatcher
-        let connection = Connection::session().await?;
+        let connection = self.shared_bus.connect(&self.bus).await?;
+        *self.connection.write().await = Some(connection.clone());
         let connection_proxy = DBusProxy::new(&connection).await?;
 
-        let notifier_watcher_name = BusName::try_from("org.freedesktop.StatusNotifierWatcher")?;
-        let kde_notifier_watcher_name = BusName::try_from("org.kde.StatusNotifierWatcher")?;
+        let notifier_watcher_name = BusName::try_from(WATCHER_NAME)?;
+        let kde_notifier_watcher_name = BusName::try_from(KDE_WATCHER_NAME)?;
 
         let (notifier_exist, kde_notifier_exist) = join!(
             connection_proxy
@@ -44,16 +484,239 @@ impl TouriSystemTray {
                 .map(|v| v.unwrap_or_default()),
         );
 
-        // If not, emulate it
-        if !notifier_exist && !kde_notifier_exist {
-            println!(
-                "Notifier not found: using emulator. Debug value: {}",
-                notifier_exist
+        // If not, emulate it. An emulator from before a reconnect still
+        // serves the names, it is kept
+        if self.emulator.read().await.is_some() {
+            Log::warn(
+                "system_tray",
+                "TouriSystemTray: reconnected, keeping emulator",
             );
+        } else if !notifier_exist && !kde_notifier_exist {
+            self.start_emulator().await;
+        } else if notifier_exist {
+            self.set_backend(TrayBackend::new("real", WATCHER_NAME))
+                .await;
+        } else {
+            self.set_backend(TrayBackend::new("real", KDE_WATCHER_NAME))
+                .await;
+        }
+
+        // First list is sent with icons resolved, not filled in one by one
+        if let Err(err) = self.prewarm().await {
+            Log::warn("system_tray", format!("TouriSystemTray prewarm err: {err}"));
+        }
+
+        self.follow_watchers(&connection_proxy).await
+    }
 
-            emulator::SystemTrayEmulator::new();
+    async fn start_emulator(&self) {
+        *self.emulator.write().await = Some(emulator::SystemTrayEmulator::new(
+            self.app_handle.clone(),
+            self.shutdown.clone(),
+            self.bus.clone(),
+            self.subscription.clone(),
+            self.reconnect.clone(),
+        ));
+        self.set_backend(TrayBackend::new("emulator", WATCHER_NAME))
+            .await;
+    }
+
+    // Watchers come and go with panels: a real one replacing the emulator
+    // gets its items (the emulator steps down by itself), and when the real
+    // one we use is gone the emulator is started
+    async fn follow_watchers(&self, connection_proxy: &DBusProxy<'_>) -> Result<()> {
+        let mut stream = connection_proxy.receive_name_owner_changed().await?;
+        let shutdown = self.shutdown.token();
+
+        while let Some(signal) = tokio::select! {
+            signal = stream.next() => signal,
+            _ = shutdown.cancelled() => None,
+        } {
+            let Ok(args) = signal.args() else {
+                continue;
+            };
+            let name = args.name().to_string();
+            if name != WATCHER_NAME && name != KDE_WATCHER_NAME {
+                continue;
+            }
+            let new_owner = args.new_owner().as_ref().map(|v| v.to_string());
+            let backend = self.backend().await;
+
+            if backend.backend == "emulator" {
+                let emulator = self.emulator.read().await.clone();
+                let ours = match emulator.as_ref() {
+                    Some(emulator) => emulator.unique_name().await,
+                    None => None,
+                };
+                if new_owner.is_some() && new_owner != ours {
+                    Log::warn(
+                        "system_tray",
+                        format!("TouriSystemTray: real watcher {name} appeared, leaving emulator"),
+                    );
+                    *self.emulator.write().await = None;
+                    self.set_backend(TrayBackend::new("real", &name)).await;
+                }
+            } else if backend.backend == "real" && name == backend.watcher_name {
+                if new_owner.is_some() {
+                    continue;
+                }
+
+                let other = match name.as_str() {
+                    WATCHER_NAME => KDE_WATCHER_NAME,
+                    _ => WATCHER_NAME,
+                };
+                let other_exists = match BusName::try_from(other) {
+                    Ok(other) => connection_proxy
+                        .name_has_owner(other)
+                        .await
+                        .unwrap_or_default(),
+                    Err(_) => false,
+                };
+
+                if other_exists {
+                    Log::warn(
+                        "system_tray",
+                        format!("TouriSystemTray: watcher {name} is gone, using {other}"),
+                    );
+                    self.set_backend(TrayBackend::new("real", other)).await;
+                } else {
+                    Log::warn(
+                        "system_tray",
+                        format!("TouriSystemTray: watcher {name} is gone, starting emulator"),
+                    );
+                    self.start_emulator().await;
+                }
+            }
         }
 
         Ok(())
     }
 }
+
+// Shared with the emulator, its item events follow the subscription too
+type Subscription = Arc<RwLock<Option<HashSet<String>>>>;
+
+fn is_subscribed(subscription: &Option<HashSet<String>>, service: &str) -> bool {
+    match subscription {
+        Some(services) => services.contains(service),
+        None => true,
+    }
+}
+
+// ToolTip is optional, (icon name, icon pixmaps, title, description)
+fn tooltip_text(value: &Value) -> (String, String) {
+    let fields = match value {
+        Value::Structure(structure) => structure.fields(),
+        _ => &[],
+    };
+    let text = |index: usize| match fields.get(index) {
+        Some(Value::Str(v)) => v.to_string(),
+        _ => String::new(),
+    };
+
+    (text(2), text(3))
+}
+
+// First non-empty of ToolTip title, Title and app name, app is empty when
+// its fallback is off
+fn pick_tooltip(tooltip: (String, String), title: String, app: String) -> TrayTooltip {
+    let (tooltip_title, description) = tooltip;
+    let (title, source) = if !tooltip_title.is_empty() {
+        (tooltip_title, "tooltip")
+    } else if !title.is_empty() {
+        (title, "title")
+    } else if !app.is_empty() {
+        (app, "app")
+    } else {
+        (String::new(), "none")
+    };
+
+    TrayTooltip {
+        title,
+        description,
+        source: source.to_string(),
+    }
+}
+
+// Service is registered as "bus name" or "bus name/object path"
+fn item_address(service: &str) -> (&str, &str) {
+    match service.find('/') {
+        Some(index) => service.split_at(index),
+        None => (service, ITEM_PATH),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_subscribed, pick_tooltip, tooltip_text};
+    use std::collections::HashSet;
+    use zvariant::{StructureBuilder, Value};
+
+    fn tooltip(title: &str, description: &str) -> Value<'static> {
+        let pixmaps: Vec<(i32, i32, Vec<u8>)> = Vec::new();
+        StructureBuilder::new()
+            .add_field(String::new())
+            .add_field(pixmaps)
+            .add_field(title.to_string())
+            .add_field(description.to_string())
+            .build()
+            .into()
+    }
+
+    #[test]
+    fn no_subscription_allows_all() {
+        assert!(is_subscribed(&None, ":1.5"));
+    }
+
+    #[test]
+    fn subscription_narrows_services() {
+        let subscription = Some(HashSet::from([":1.5".to_string(), "unknown".to_string()]));
+        assert!(is_subscribed(&subscription, ":1.5"));
+        assert!(!is_subscribed(&subscription, ":1.6"));
+    }
+
+    #[test]
+    fn tooltip_text_reads_title_and_description() {
+        let text = tooltip_text(&tooltip("Volume", "50%"));
+        assert_eq!(text, ("Volume".to_string(), "50%".to_string()));
+        assert_eq!(tooltip_text(&Value::from("x")), Default::default());
+    }
+
+    #[test]
+    fn tooltip_wins_over_fallbacks() {
+        let text = ("Volume".to_string(), "50%".to_string());
+        let tooltip = pick_tooltip(text, "Title".into(), "app".into());
+        assert_eq!(
+            (tooltip.title.as_str(), tooltip.source.as_str()),
+            ("Volume", "tooltip")
+        );
+        assert_eq!(tooltip.description, "50%");
+    }
+
+    #[test]
+    fn empty_tooltip_falls_back_to_title() {
+        let tooltip = pick_tooltip(Default::default(), "Title".into(), "app".into());
+        assert_eq!(
+            (tooltip.title.as_str(), tooltip.source.as_str()),
+            ("Title", "title")
+        );
+    }
+
+    #[test]
+    fn empty_title_falls_back_to_app() {
+        let tooltip = pick_tooltip(Default::default(), String::new(), "nm-applet".into());
+        assert_eq!(
+            (tooltip.title.as_str(), tooltip.source.as_str()),
+            ("nm-applet", "app")
+        );
+    }
+
+    #[test]
+    fn nothing_left_is_none() {
+        let tooltip = pick_tooltip(Default::default(), String::new(), String::new());
+        assert_eq!(
+            (tooltip.title.as_str(), tooltip.source.as_str()),
+            ("", "none")
+        );
+    }
+}
@@ -1,6 +1,18 @@
+mod Battery;
+mod Bus;
+mod DBusBridge;
+mod Emit;
+mod FocusedWindow;
+mod IconTheme;
+mod Log;
 mod MediaPlayer;
+mod Modules;
+mod Shutdown;
 mod SystemTray;
 
+use std::sync::Arc;
+use tauri::{Manager, RunEvent};
+
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
 #[tauri::command]
 fn greet(name: &str) -> String {
@@ -13,13 +25,123 @@ pub fn run() {
 
     tauri::Builder::default()
         .setup(|app| {
-            let _ = SystemTray::TouriSystemTray::new();
-            let _ = MediaPlayer::TauriMediaPlayer::new(app.handle().clone());
+            Log::init(app.handle().clone());
+            let shutdown = Shutdown::Shutdown::new();
+            app.manage(shutdown.clone());
+            let buses = Bus::BusPreferences::load();
+            let shared_bus = Bus::SharedBus::new();
+            app.manage(Arc::new(Emit::EmitTargets::default()));
+
+            app.manage(SystemTray::TouriSystemTray::new(
+                app.handle().clone(),
+                shutdown.clone(),
+                buses.system_tray,
+                shared_bus.clone(),
+                buses.reconnect.clone(),
+            ));
+            app.manage(MediaPlayer::TauriMediaPlayer::new(
+                app.handle().clone(),
+                shutdown.clone(),
+                buses.media_player,
+                shared_bus.clone(),
+                buses.reconnect.clone(),
+            ));
+            app.manage(DBusBridge::TauriDBusBridge::new(
+                app.handle().clone(),
+                shutdown.clone(),
+                buses.dbus_bridge,
+                buses.reconnect.clone(),
+            ));
+            app.manage(Battery::TauriBattery::new(
+                app.handle().clone(),
+                shutdown.clone(),
+                buses.battery,
+                shared_bus,
+                buses.reconnect,
+            ));
+            app.manage(FocusedWindow::TauriFocusedWindow::new(
+                app.handle().clone(),
+                shutdown,
+            ));
 
             Ok(())
         })
         .plugin(tauri_plugin_opener::init())
-        .invoke_handler(tauri::generate_handler![greet])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .invoke_handler(tauri::generate_handler![
+            greet,
+            MediaPlayer::commands::media_pin_player,
+            MediaPlayer::commands::media_unpin,
+            MediaPlayer::commands::media_set_preferred_player,
+            MediaPlayer::commands::media_get_pinned_player,
+            MediaPlayer::commands::media_get_config,
+            MediaPlayer::commands::media_set_config,
+            MediaPlayer::commands::media_set_max_text_length,
+            MediaPlayer::commands::media_set_now_playing_template,
+            MediaPlayer::commands::media_get_players,
+            MediaPlayer::commands::media_playing_players,
+            MediaPlayer::commands::media_status_history,
+            MediaPlayer::commands::media_inject,
+            MediaPlayer::commands::media_metrics,
+            MediaPlayer::commands::media_keys_enable,
+            MediaPlayer::commands::media_keys_disable,
+            MediaPlayer::commands::media_get_position,
+            MediaPlayer::commands::media_progress,
+            MediaPlayer::commands::media_full_snapshot,
+            MediaPlayer::commands::media_raise,
+            MediaPlayer::commands::media_play_pause,
+            MediaPlayer::commands::media_play,
+            MediaPlayer::commands::media_pause,
+            MediaPlayer::commands::media_stop,
+            MediaPlayer::commands::media_next,
+            MediaPlayer::commands::media_previous,
+            MediaPlayer::commands::media_seek,
+            MediaPlayer::commands::media_set_volume,
+            MediaPlayer::commands::media_set_rate,
+            MediaPlayer::commands::media_toggle_shuffle,
+            MediaPlayer::commands::media_cycle_loop,
+            SystemTray::commands::tray_debug_state,
+            SystemTray::commands::tray_clear_items,
+            SystemTray::commands::tray_get_items,
+            SystemTray::commands::tray_emit_full,
+            SystemTray::commands::tray_prewarm,
+            SystemTray::commands::tray_subscribe,
+            SystemTray::commands::tray_subscribe_all,
+            SystemTray::commands::tray_get_tooltip,
+            SystemTray::commands::tray_set_tooltip_fallback,
+            SystemTray::commands::tray_set_fallback_icon,
+            SystemTray::commands::tray_set_hidden,
+            SystemTray::commands::tray_get_property,
+            SystemTray::commands::tray_activate,
+            SystemTray::commands::tray_secondary_activate,
+            SystemTray::commands::tray_scroll,
+            SystemTray::commands::tray_menu_open,
+            SystemTray::commands::tray_menu_close,
+            SystemTray::commands::tray_menu_refresh,
+            SystemTray::commands::tray_menu_activate,
+            SystemTray::commands::tray_menu_click,
+            DBusBridge::commands::dbus_add_match,
+            DBusBridge::commands::dbus_remove_match,
+            IconTheme::commands::resolve_icon,
+            Battery::commands::battery_get_state,
+            Battery::commands::battery_get_config,
+            Battery::commands::battery_set_config,
+            FocusedWindow::commands::focused_window_get,
+            Modules::commands::list_modules,
+            Modules::commands::restart_module,
+            Bus::commands::module_get_buses,
+            Bus::commands::module_set_bus,
+            Bus::commands::module_set_reconnect_policy,
+            Emit::commands::module_get_emit_targets,
+            Emit::commands::module_set_emit_target,
+        ])
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app_handle, event| {
+            // Stop module tasks before the process is gone:
+            if let RunEvent::Exit = event {
+                if let Some(shutdown) = app_handle.try_state::<Arc<Shutdown::Shutdown>>() {
+                    shutdown.trigger();
+                }
+            }
+        });
 }